@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE games ADD COLUMN IF NOT EXISTS variant TEXT NOT NULL DEFAULT 'connect-four';
@@ -0,0 +1,28 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS quote_revisions (
+    quote_id UUID NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    version INT NOT NULL,
+    author TEXT NOT NULL,
+    quote TEXT NOT NULL,
+    revised_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (quote_id, version)
+);
+
+-- Every insert and every edit of a quote is kept as a revision, whichever query made it.
+CREATE OR REPLACE FUNCTION record_quote_revision() RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO quote_revisions (quote_id, version, author, quote)
+        VALUES (NEW.id, NEW.version, NEW.author, NEW.quote)
+        ON CONFLICT (quote_id, version) DO UPDATE
+            SET author = EXCLUDED.author, quote = EXCLUDED.quote, revised_at = EXCLUDED.revised_at;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS quotes_revision ON quotes;
+CREATE TRIGGER quotes_revision AFTER INSERT OR UPDATE OF author, quote, version ON quotes
+    FOR EACH ROW EXECUTE FUNCTION record_quote_revision();
+
+INSERT INTO quote_revisions (quote_id, version, author, quote, revised_at)
+    SELECT id, version, author, quote, created_at FROM quotes
+    ON CONFLICT DO NOTHING;
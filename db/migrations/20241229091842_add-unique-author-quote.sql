@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Duplicates drafted before the constraint go to the trash, the oldest copy stays live.
+UPDATE quotes SET deleted_at = CURRENT_TIMESTAMP
+    WHERE deleted_at IS NULL AND EXISTS (
+        SELECT 1 FROM quotes older
+        WHERE older.deleted_at IS NULL
+            AND older.author = quotes.author AND older.quote = quotes.quote
+            AND (older.created_at, older.id) < (quotes.created_at, quotes.id)
+    );
+
+CREATE UNIQUE INDEX IF NOT EXISTS quotes_author_quote_key ON quotes (author, md5(quote)) WHERE deleted_at IS NULL;
@@ -0,0 +1,20 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS tags (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS quote_tags (
+    quote_id UUID NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    tag_id INT NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+    PRIMARY KEY (quote_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS quote_tags_tag_id_idx ON quote_tags (tag_id);
+
+-- The sorted tag names of a quote, so that every query returning quotes can add them as a column.
+CREATE OR REPLACE FUNCTION quote_tag_names(quote_id UUID) RETURNS TEXT[] AS $$
+    SELECT COALESCE(array_agg(tags.name ORDER BY tags.name), '{}')
+    FROM quote_tags JOIN tags ON tags.id = quote_tags.tag_id
+    WHERE quote_tags.quote_id = $1;
+$$ LANGUAGE SQL STABLE;
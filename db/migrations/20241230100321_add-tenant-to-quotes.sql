@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS tenant TEXT NOT NULL DEFAULT 'default';
+
+DROP INDEX IF EXISTS quotes_author_quote_key;
+CREATE UNIQUE INDEX IF NOT EXISTS quotes_author_quote_key ON quotes (tenant, author, md5(quote)) WHERE deleted_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS quotes_tenant_created_at_idx ON quotes (tenant, created_at, id);
@@ -0,0 +1,27 @@
+-- Add migration script here
+-- Tells the `quote_changes` listeners which quote was created, updated or sent to the trash, whichever query
+-- did it. They read the quote itself once the change committed, as a notification can't carry much.
+CREATE OR REPLACE FUNCTION notify_quote_change() RETURNS TRIGGER AS $$
+DECLARE
+    op TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        op := CASE WHEN NEW.deleted_at IS NULL THEN 'create' END;
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        op := 'delete';
+    ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        -- A quote restored from the trash is created again.
+        op := 'create';
+    ELSIF NEW.deleted_at IS NULL AND (OLD.author, OLD.quote, OLD.version) IS DISTINCT FROM (NEW.author, NEW.quote, NEW.version) THEN
+        op := 'update';
+    END IF;
+    IF op IS NOT NULL THEN
+        PERFORM pg_notify('quote_changes', json_build_object('op', op, 'id', NEW.id, 'tenant', NEW.tenant)::TEXT);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS quotes_notify ON quotes;
+CREATE TRIGGER quotes_notify AFTER INSERT OR UPDATE OF author, quote, version, deleted_at ON quotes
+    FOR EACH ROW EXECUTE FUNCTION notify_quote_change();
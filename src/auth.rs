@@ -0,0 +1,405 @@
+use std::{collections::HashMap, future::{ready, Ready}, str::FromStr};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, Method},
+    web, Error, HttpMessage, HttpResponse
+};
+use futures_util::future::LocalBoxFuture;
+use jsonwebtoken::{decode_header, Algorithm, Validation};
+use serde::Deserialize;
+
+use crate::challenges::{day_16::GiftKeys, day_19::valid_tenant};
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+/// Header `typ` of the access tokens. `/16/wrap` signs with the same keys but never with this
+/// type, so a wrapped gift can't be replayed as an access token.
+pub const ACCESS_TOKEN_TYPE: &str = "at+jwt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    Editor,
+    Admin
+}
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role.trim().to_lowercase().as_str() {
+            "reader" => Ok(Role::Reader),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role: {}", role))
+        }
+    }
+}
+
+/// The authenticated caller, stored in the request extensions for the handlers.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Principal {
+    pub sub: String,
+    pub role: Role,
+    #[serde(default)]
+    pub tenant: Option<String>
+}
+
+/// Recognizes callers from an `X-Api-Key` header or a bearer JWT signed with one of the gift keys.
+pub struct Authenticator {
+    api_keys: HashMap<String, Principal>,
+    gift_keys: Option<web::Data<GiftKeys>>,
+    issuer: String,
+    kids: Vec<String>,
+    audience: Option<String>,
+    anonymous: Option<Role>
+}
+impl Authenticator {
+    /// Reads API keys as comma separated `key=role` or `key=role:tenant` entries.
+    pub fn new(api_keys: &str) -> Result<Self, String> {
+        let mut authenticator = Authenticator {
+            api_keys: HashMap::new(),
+            gift_keys: None,
+            issuer: String::new(),
+            kids: vec![],
+            audience: None,
+            anonymous: None
+        };
+        for (i, entry) in api_keys.split(',').map(str::trim).filter(|entry| !entry.is_empty()).enumerate() {
+            let Some((key, grant)) = entry.split_once('=') else {
+                return Err(format!("API key entry {} has no role", i + 1));
+            };
+            let (role, tenant) = match grant.split_once(':') {
+                None => (grant, None),
+                Some((role, tenant)) if valid_tenant(tenant.trim()) => (role, Some(tenant.trim().to_string())),
+                Some(_) => return Err(format!("API key entry {} has an invalid tenant", i + 1))
+            };
+            let principal = Principal { sub: format!("api-key-{}", i + 1), role: role.parse()?, tenant };
+            authenticator.api_keys.insert(key.trim().to_string(), principal);
+        }
+        Ok(authenticator)
+    }
+
+    /// Also accepts bearer tokens of type `at+jwt` from `issuer`, with `aud` checked when set. Only the
+    /// gift keys listed in `kids` sign access tokens: the others, such as the third party keys of
+    /// `PUB_PEM` or a JWKS, and the HMAC secret, which has no `kid`, only ever verify gifts.
+    pub fn with_jwt(mut self, gift_keys: web::Data<GiftKeys>, issuer: String, kids: Vec<String>, audience: Option<String>) -> Self {
+        self.gift_keys = Some(gift_keys);
+        self.issuer = issuer;
+        self.kids = kids;
+        self.audience = audience;
+        self
+    }
+
+    /// Role of the callers without credentials, none by default.
+    pub fn with_anonymous(mut self, role: Option<Role>) -> Self {
+        self.anonymous = role;
+        self
+    }
+
+    /// `Ok(None)` for callers without credentials.
+    fn authenticate(&self, req: &ServiceRequest) -> Result<Option<Principal>, &'static str> {
+        if let Some(key) = req.headers().get(API_KEY_HEADER) {
+            return match key.to_str().ok().and_then(|key| self.api_keys.get(key)) {
+                None => Err("Unknown API key"),
+                Some(principal) => Ok(Some(principal.clone()))
+            };
+        }
+
+        let token = match req.headers().get(header::AUTHORIZATION).map(|value| value.to_str()) {
+            None => return Ok(None),
+            Some(Err(_)) => return Err("Malformed authorization header"),
+            Some(Ok(value)) => match value.strip_prefix("Bearer ") {
+                None => return Err("Only bearer tokens are accepted"),
+                Some(token) => token.trim()
+            }
+        };
+        let Some(gift_keys) = &self.gift_keys else {
+            return Err("Bearer tokens are not accepted");
+        };
+        let header = decode_header(token).map_err(|_| "Malformed token")?;
+        if !header.typ.as_deref().is_some_and(|typ| typ.eq_ignore_ascii_case(ACCESS_TOKEN_TYPE)) {
+            return Err("Not an access token");
+        }
+        if !header.kid.as_ref().is_some_and(|kid| self.kids.contains(kid)) {
+            return Err("Not signed with an access token key");
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = vec![
+            Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+            Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+            Algorithm::ES256, Algorithm::ES384, Algorithm::EdDSA
+        ];
+        validation.leeway = 0;
+        validation.set_required_spec_claims(&["exp", "sub", "iss"]);
+        validation.set_issuer(&[&self.issuer]);
+        match &self.audience {
+            None => validation.validate_aud = false,
+            Some(audience) => validation.set_audience(&[audience])
+        }
+        match gift_keys.decode::<Principal>(token, &validation) {
+            Err(_) => Err("Invalid token"),
+            Ok(data) => Ok(Some(data.claims))
+        }
+    }
+}
+
+/// The role each route of a scope needs: the first rule whose method and path prefix match wins,
+/// routes without a rule fall back to `rest`, and are public when there's none.
+#[derive(Clone, Default)]
+pub struct Policy {
+    /// `None` for the routes open to every caller.
+    rules: Vec<(Method, String, Option<Role>)>,
+    rest: Option<Role>
+}
+impl Policy {
+    pub fn new() -> Self {
+        Policy::default()
+    }
+
+    pub fn route(mut self, method: Method, prefix: &str, role: Role) -> Self {
+        self.rules.push((method, prefix.to_string(), Some(role)));
+        self
+    }
+
+    /// Keeps the matching routes public whatever `rest` requires.
+    pub fn open(mut self, method: Method, prefix: &str) -> Self {
+        self.rules.push((method, prefix.to_string(), None));
+        self
+    }
+
+    pub fn rest(mut self, role: Role) -> Self {
+        self.rest = Some(role);
+        self
+    }
+
+    fn required(&self, method: &Method, path: &str) -> Option<Role> {
+        self.rules
+            .iter()
+            .find(|(m, prefix, _)| m == method && path.starts_with(prefix.as_str()))
+            .map_or(self.rest, |(_, _, role)| *role)
+    }
+
+    /// Authenticates the caller and lets the request through when its role is high enough.
+    fn check(&self, req: &ServiceRequest) -> Result<(), HttpResponse> {
+        let authenticator = req.app_data::<web::Data<Authenticator>>();
+        let principal = match authenticator.map(|a| a.authenticate(req)) {
+            None | Some(Ok(None)) => None,
+            Some(Ok(Some(principal))) => Some(principal),
+            Some(Err(reason)) => return Err(unauthorized(reason))
+        };
+        let role = principal.as_ref().map(|p| p.role).or(authenticator.and_then(|a| a.anonymous));
+        if let Some(principal) = principal {
+            req.extensions_mut().insert(principal);
+        }
+        // Handlers with finer grained permissions check the role themselves.
+        if let Some(role) = role {
+            req.extensions_mut().insert(role);
+        }
+
+        // The router matches the decoded path, so `/12/%72eset` is routed to `/12/reset`.
+        match (self.required(req.method(), req.match_info().as_str()), role) {
+            (None, _) => Ok(()),
+            (Some(_), None) => Err(unauthorized("Authentication required")),
+            (Some(required), Some(role)) if role < required => Err(HttpResponse::Forbidden().body(format!("Requires the {:?} role", required))),
+            _ => Ok(())
+        }
+    }
+}
+
+fn unauthorized(reason: &'static str) -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+        .body(reason)
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Policy
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = PolicyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PolicyMiddleware { service, policy: self.clone() }))
+    }
+}
+
+pub struct PolicyMiddleware<S> {
+    service: S,
+    policy: Policy
+}
+impl<S, B> Service<ServiceRequest> for PolicyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        match self.policy.check(&req) {
+            Err(response) => {
+                let response = req.into_response(response).map_into_right_body();
+                Box::pin(async { Ok(response) })
+            },
+            Ok(()) => {
+                let response = self.service.call(req);
+                Box::pin(async move { response.await.map(ServiceResponse::map_into_left_body) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::{Method, StatusCode}, post, test::{self, TestRequest}, web, App, HttpResponse};
+    use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+    use p256::pkcs8::{EncodePrivateKey, LineEnding};
+    use serde_json::{json, Value};
+
+    use super::{Authenticator, Policy, Principal, Role, ACCESS_TOKEN_TYPE, API_KEY_HEADER};
+    use crate::challenges::day_16::GiftKeys;
+
+    const ISSUER: &str = "https://auth.example";
+
+    /// An authenticator trusting a freshly generated EC key, along with that key and its `kid`.
+    fn authenticator() -> (Authenticator, EncodingKey, String) {
+        let pem = p256::SecretKey::random(&mut rand::thread_rng()).to_pkcs8_pem(LineEnding::LF).unwrap();
+        let keys = GiftKeys::from_pems(b"secret", Some(&pem), "").unwrap();
+        let kid = keys.jwks().keys[0].common.key_id.clone().unwrap();
+        let authenticator = Authenticator::new("")
+            .unwrap()
+            .with_jwt(web::Data::new(keys), ISSUER.to_string(), vec![kid.clone()], None);
+        (authenticator, EncodingKey::from_ec_pem(pem.as_bytes()).unwrap(), kid)
+    }
+
+    fn token(key: &EncodingKey, typ: Option<&str>, kid: &str, claims: Value) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.typ = typ.map(str::to_string);
+        header.kid = Some(kid.to_string());
+        encode(&header, &claims, key).unwrap()
+    }
+
+    fn claims() -> Value {
+        json!({ "sub": "santa", "role": "editor", "iss": ISSUER, "exp": get_current_timestamp() + 60 })
+    }
+
+    fn authenticate(authenticator: &Authenticator, header: Option<(&str, &str)>) -> Result<Option<Principal>, &'static str> {
+        let request = match header {
+            None => TestRequest::default(),
+            Some(header) => TestRequest::default().insert_header(header)
+        };
+        authenticator.authenticate(&request.to_srv_request())
+    }
+
+    fn bearer(token: &str) -> String {
+        format!("Bearer {}", token)
+    }
+
+    #[test]
+    fn roles_are_ordered() {
+        assert!(Role::Reader < Role::Editor && Role::Editor < Role::Admin);
+        assert_eq!(" Admin ".parse::<Role>(), Ok(Role::Admin));
+        assert!("owner".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn api_keys_are_parsed() {
+        let authenticator = Authenticator::new("k1=reader, k2 = editor:red ,").unwrap();
+
+        let reader = authenticate(&authenticator, Some((API_KEY_HEADER, "k1"))).unwrap().unwrap();
+        assert_eq!((reader.sub.as_str(), reader.role, reader.tenant), ("api-key-1", Role::Reader, None));
+        let editor = authenticate(&authenticator, Some((API_KEY_HEADER, "k2"))).unwrap().unwrap();
+        assert_eq!((editor.role, editor.tenant.as_deref()), (Role::Editor, Some("red")));
+
+        assert_eq!(authenticate(&authenticator, Some((API_KEY_HEADER, "k3"))).unwrap_err(), "Unknown API key");
+        assert!(authenticate(&authenticator, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_api_keys_are_refused() {
+        assert!(Authenticator::new("k1").is_err());
+        assert!(Authenticator::new("k1=owner").is_err());
+        assert!(Authenticator::new("k1=reader:red, k2").is_err());
+        assert!(Authenticator::new("k1=editor:").is_err());
+        assert!(Authenticator::new("k1=editor:red team").is_err());
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let policy = Policy::new()
+            .route(Method::POST, "/19/reset", Role::Admin)
+            .open(Method::POST, "/19/graphql")
+            .open(Method::GET, "/19")
+            .route(Method::GET, "/19/trash", Role::Admin)
+            .rest(Role::Editor);
+        assert_eq!(policy.required(&Method::POST, "/19/reset"), Some(Role::Admin));
+        assert_eq!(policy.required(&Method::POST, "/19/graphql"), None);
+        assert_eq!(policy.required(&Method::GET, "/19/trash"), None);
+        assert_eq!(policy.required(&Method::POST, "/19/draft"), Some(Role::Editor));
+        assert_eq!(policy.required(&Method::DELETE, "/19/reset"), Some(Role::Editor));
+    }
+
+    #[post("/reset")]
+    async fn reset() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn encoded_paths_follow_the_same_rules() {
+        let app = test::init_service(App::new().service(web::scope("/12")
+            .wrap(Policy::new().route(Method::POST, "/12/reset", Role::Admin))
+            .service(reset))).await;
+        for path in ["/12/reset", "/12/%72eset", "/12/re%73et"] {
+            let response = test::call_service(&app, TestRequest::post().uri(path).to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", path);
+        }
+    }
+
+    #[test]
+    fn access_token_is_accepted() {
+        let (authenticator, key, kid) = authenticator();
+        let token = token(&key, Some(ACCESS_TOKEN_TYPE), &kid, claims());
+        let principal = authenticate(&authenticator, Some(("Authorization", &bearer(&token)))).unwrap().unwrap();
+        assert_eq!((principal.sub.as_str(), principal.role), ("santa", Role::Editor));
+    }
+
+    #[test]
+    fn only_access_tokens_are_accepted() {
+        let (authenticator, key, kid) = authenticator();
+        for typ in [None, Some("JWT")] {
+            let token = token(&key, typ, &kid, claims());
+            assert_eq!(authenticate(&authenticator, Some(("Authorization", &bearer(&token)))).unwrap_err(), "Not an access token");
+        }
+    }
+
+    #[test]
+    fn access_token_needs_a_listed_kid_and_the_issuer() {
+        let (authenticator, key, kid) = authenticator();
+        let token_of = |kid: &str, claims| bearer(&token(&key, Some(ACCESS_TOKEN_TYPE), kid, claims));
+
+        let unlisted = token_of("other", claims());
+        assert_eq!(authenticate(&authenticator, Some(("Authorization", &unlisted))).unwrap_err(), "Not signed with an access token key");
+
+        let mut foreign = claims();
+        foreign["iss"] = "https://elsewhere.example".into();
+        let mut anonymous = claims();
+        anonymous.as_object_mut().unwrap().remove("iss");
+        for claims in [foreign, anonymous] {
+            let token = token_of(&kid, claims);
+            assert_eq!(authenticate(&authenticator, Some(("Authorization", &token))).unwrap_err(), "Invalid token");
+        }
+    }
+}
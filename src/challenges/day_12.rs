@@ -1,9 +1,10 @@
 use std::{error::Error, fmt::Display, ops::Sub};
 
 use actix_web::{web, Scope};
-use serde::Deserialize;
+use serde::{ser::SerializeStruct, Deserialize, Serialize};
+use sqlx::{prelude::FromRow, types::{chrono::{DateTime, Utc}, Uuid}};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq)]
 pub enum Team {
     #[serde(rename = "milk")]
     Milk,
@@ -20,6 +21,14 @@ impl TryFrom<String> for Team {
         }
     }
 }
+impl Team {
+    fn name(&self) -> &'static str {
+        match self {
+            Team::Cookie => "cookie",
+            Team::Milk => "milk",
+        }
+    }
+}
 impl Display for Team {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -44,112 +53,251 @@ impl Display for TileState {
         }
     }
 }
+impl From<Team> for TileState {
+    fn from(team: Team) -> Self {
+        match team {
+            Team::Cookie => TileState::Cookie,
+            Team::Milk => TileState::Milk,
+        }
+    }
+}
+impl TileState {
+    fn team(&self) -> Option<Team> {
+        match self {
+            TileState::Empty => None,
+            TileState::Cookie => Some(Team::Cookie),
+            TileState::Milk => Some(Team::Milk),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Copy, Clone)]
+pub enum Direction {
+    #[serde(rename = "row")]
+    Row,
+    #[serde(rename = "column")]
+    Column,
+    #[serde(rename = "diagonal")]
+    Diagonal
+}
+
+/// Grid coordinate, 0-based internally and exposed 1-based (row 1 is the bottom one)
+/// so it matches the column numbers used by `/12/place`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cell {
+    column: usize,
+    row: usize
+}
+impl Cell {
+    fn new(column: usize, row: usize) -> Self {
+        Cell { column, row }
+    }
+}
+impl Serialize for Cell {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut cell = serializer.serialize_struct("Cell", 2)?;
+        cell.serialize_field("column", &(self.column + 1))?;
+        cell.serialize_field("row", &(self.row + 1))?;
+        cell.end()
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Win {
+    team: Team,
+    direction: Direction,
+    cells: Vec<Cell>
+}
+
+/// Rule set the board is played with, chosen on `/12/reset`.
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Default)]
+pub enum Variant {
+    /// Pieces drop to the lowest empty cell of a 4x4 grid.
+    #[default]
+    #[serde(rename = "connect-four")]
+    ConnectFour,
+    /// Pieces are placed on any empty cell of a 4x4 grid.
+    #[serde(rename = "free")]
+    GravityFree,
+    /// Pieces are placed on any empty cell of a 3x3 grid.
+    #[serde(rename = "tic-tac-toe")]
+    TicTacToe,
+    /// Connect Four where a team may also pop its own piece out of the bottom row.
+    #[serde(rename = "pop-out")]
+    PopOut
+}
+impl Variant {
+    fn name(&self) -> &'static str {
+        match self {
+            Variant::ConnectFour => "connect-four",
+            Variant::GravityFree => "free",
+            Variant::TicTacToe => "tic-tac-toe",
+            Variant::PopOut => "pop-out",
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Variant::TicTacToe => 3,
+            _ => 4
+        }
+    }
+
+    pub fn accepts(&self, play: &Move) -> bool {
+        matches!(
+            (self, play),
+            (Variant::ConnectFour | Variant::PopOut, Move::Drop(_)) |
+            (Variant::PopOut, Move::Pop(_)) |
+            (Variant::GravityFree | Variant::TicTacToe, Move::Place(_, _))
+        )
+    }
+
+    /// A full pop-out board is not over, the teams can still pop pieces out.
+    fn ends_when_full(&self) -> bool {
+        !matches!(self, Variant::PopOut)
+    }
+}
+
+/// A move on the board, columns and rows are 1-based with row 1 at the bottom.
+#[derive(Debug, Copy, Clone)]
+pub enum Move {
+    Drop(usize),
+    Place(usize, usize),
+    Pop(usize)
+}
+
 #[derive(Debug)]
 pub struct Board {
-    grid: [[TileState;4];4],
+    variant: Variant,
+    grid: Vec<Vec<TileState>>,
     raw_representation: String,
-    winner: Option<Team>
+    winner: Option<Win>,
+    last_team: Option<Team>,
+    moves: i32,
+    /// When the first move was played, so idle time before a game doesn't count in its duration.
+    started_at: DateTime<Utc>
 }
 impl Board {
     pub fn new() -> Self {
-        Board {
-            grid: [[TileState::Empty;4];4],
-            raw_representation: r#"⬜⬛⬛⬛⬛⬜
-⬜⬛⬛⬛⬛⬜
-⬜⬛⬛⬛⬛⬜
-⬜⬛⬛⬛⬛⬜
-⬜⬜⬜⬜⬜⬜
-"#.to_string(),
-            winner: None
+        Board::with_variant(Variant::default())
+    }
+
+    pub fn with_variant(variant: Variant) -> Self {
+        let mut board = Board {
+            variant,
+            grid: vec![vec![TileState::Empty; variant.size()]; variant.size()],
+            raw_representation: String::new(),
+            winner: None,
+            last_team: None,
+            moves: 0,
+            started_at: Utc::now()
+        };
+        board.grid_update();
+        board
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn play(&mut self, team: &Team, play: Move) -> Result<(), Box<dyn Error>> {
+        match play {
+            Move::Drop(column) => return self.set_position(team, column),
+            Move::Place(column, row) => {
+                let tile = &mut self.grid[column.sub(1)][row.sub(1)];
+                if !matches!(tile, TileState::Empty) {
+                    return Err(From::from("Occupied cell"));
+                }
+                *tile = TileState::from(*team);
+            },
+            Move::Pop(column) => {
+                let column = &mut self.grid[column.sub(1)];
+                if column[0].team() != Some(*team) {
+                    return Err(From::from("Bottom piece is not from the team"));
+                }
+                column.remove(0);
+                column.push(TileState::Empty);
+            }
         }
+        self.moved(team);
+        Ok(())
     }
 
     pub fn set_position(&mut self, team: &Team, mut column: usize) -> Result<(), Box<dyn Error>> {
         column = column.sub(1);
         if let
             Some(r) =
-            self.grid[column as usize]
+            self.grid[column]
                 .iter()
                 .position(|t| matches!(*t, TileState::Empty))
         {
-            match team {
-                Team::Cookie => self.grid[column as usize][r] = TileState::Cookie,
-                Team::Milk => self.grid[column as usize][r] = TileState::Milk,
-            }
+            self.grid[column][r] = TileState::from(*team);
+            self.moved(team);
             Ok(())
         } else {
             Err(From::from("Full column"))
         }
     }
 
-    pub fn grid_update(&mut self) {
-        self.raw_representation = format!(
-            "⬜{}{}{}{}⬜\n⬜{}{}{}{}⬜\n⬜{}{}{}{}⬜\n⬜{}{}{}{}⬜\n⬜⬜⬜⬜⬜⬜\n",
-            self.grid[0][3],self.grid[1][3],self.grid[2][3],self.grid[3][3],
-            self.grid[0][2],self.grid[1][2],self.grid[2][2],self.grid[3][2],
-            self.grid[0][1],self.grid[1][1],self.grid[2][1],self.grid[3][1],
-            self.grid[0][0],self.grid[1][0],self.grid[2][0],self.grid[3][0],
-        );
+    fn moved(&mut self, team: &Team) {
+        if self.moves == 0 {
+            self.started_at = Utc::now();
+        }
+        self.moves += 1;
+        self.last_team = Some(*team);
     }
 
-    fn column_winner(&self) -> bool {
-        self.grid
-            .iter()
-            .any(
-                |column| {
-                    column.iter().all(|t| matches!(t, TileState::Milk)) ||
-                    column.iter().all(|t| matches!(t, TileState::Cookie))
-                }
-            )
+    pub fn grid_update(&mut self) {
+        self.raw_representation = self.render(None);
     }
 
-    fn row_winner(&self) -> bool {
-        let mut rows: Vec<Vec<TileState>> = Vec::from([vec![], vec![], vec![], vec![]]);
-        for c in self.grid {
-            for (ri, r) in c.iter().enumerate() {
-                rows[ri].push(*r);
+    /// Renders the grid, drawing the cells of `highlight` as ⭐ instead of the team tile.
+    fn render(&self, highlight: Option<&Win>) -> String {
+        let size = self.variant.size();
+        let mut representation = String::new();
+        for r in (0..size).rev() {
+            representation.push('⬜');
+            for c in 0..size {
+                match highlight {
+                    Some(win) if win.cells.contains(&Cell::new(c, r)) => representation.push('⭐'),
+                    _ => representation.push_str(&self.grid[c][r].to_string())
+                }
             }
+            representation.push_str("⬜\n");
         }
-
-        rows.iter().any(
-                |row| {
-                    row.iter().all(|t| matches!(t, TileState::Milk)) ||
-                    row.iter().all(|t| matches!(t, TileState::Cookie))
-                }
-            )
+        representation.push_str(&"⬜".repeat(size + 2));
+        representation.push('\n');
+        representation
     }
 
-    fn diagonal_winner(&self) -> bool {
-        let right_top_left_down = [
-            self.grid[0][3], self.grid[1][2], self.grid[2][1], self.grid[3][0]
-        ];
-
-        if
-            right_top_left_down.iter().all(|t| matches!(t, TileState::Milk)) ||
-            right_top_left_down.iter().all(|t| matches!(t, TileState::Cookie))
-        {
-            return true;
+    fn lines(size: usize) -> Vec<(Direction, Vec<Cell>)> {
+        let mut lines = vec![];
+        for c in 0..size {
+            lines.push((Direction::Column, (0..size).map(|r| Cell::new(c, r)).collect()));
         }
-
-        let right_down_left_top = [
-            self.grid[0][0], self.grid[1][1], self.grid[2][2], self.grid[3][3]
-        ];
-
-        right_down_left_top.iter().all(|t| matches!(t, TileState::Milk)) ||
-        right_down_left_top.iter().all(|t| matches!(t, TileState::Cookie))
+        for r in 0..size {
+            lines.push((Direction::Row, (0..size).map(|c| Cell::new(c, r)).collect()));
+        }
+        lines.push((Direction::Diagonal, (0..size).map(|i| Cell::new(i, i)).collect()));
+        lines.push((Direction::Diagonal, (0..size).map(|i| Cell::new(i, size - 1 - i)).collect()));
+        lines
     }
 
-    pub fn winner(&self) -> bool {
-        if self.column_winner() == true {
-            return true;
-        }
-        if self.row_winner() == true {
-            return true;
-        }
-        if self.diagonal_winner() == true {
-            return true;
+    /// Finds a completed line. A pop can complete lines for both teams at once,
+    /// in which case the team that made the last move wins.
+    pub fn winner(&self) -> Option<Win> {
+        let mut wins = Board::lines(self.variant.size()).into_iter().filter_map(|(direction, cells)| {
+            let team = self.grid[cells[0].column][cells[0].row].team()?;
+            if cells.iter().all(|c| self.grid[c.column][c.row].team() == Some(team)) {
+                return Some(Win { team, direction, cells });
+            }
+            None
+        }).collect::<Vec<Win>>();
+
+        match wins.iter().position(|w| Some(w.team) == self.last_team) {
+            Some(i) => Some(wins.swap_remove(i)),
+            None => wins.pop()
         }
-        false
     }
 
     pub fn full(&self) -> bool {
@@ -161,74 +309,276 @@ impl Board {
                 }
             )
     }
+
+    pub fn draw(&self) -> bool {
+        self.winner.is_none() && self.variant.ends_when_full() && self.full()
+    }
+}
+
+/// Structured view of the board, served when the client asks for `application/json`.
+#[derive(Serialize, Debug)]
+pub struct BoardResult {
+    board: String,
+    winner: Option<Win>,
+    draw: bool
+}
+impl From<&Board> for BoardResult {
+    fn from(board: &Board) -> Self {
+        BoardResult {
+            board: board.render(board.winner.as_ref()),
+            winner: board.winner.clone(),
+            draw: board.draw()
+        }
+    }
+}
+
+/// A finished game as persisted in the `games` table.
+#[derive(Debug, Clone, FromRow)]
+pub struct Game {
+    id: Uuid,
+    variant: String,
+    winner: Option<String>,
+    moves: i32,
+    draw: bool,
+    started_at: DateTime<Utc>,
+    finished_at: DateTime<Utc>
+}
+impl Game {
+    /// Snapshot of the board once it has a winner or is a draw, `None` while still in play.
+    fn finished(board: &Board) -> Option<Self> {
+        let draw = board.draw();
+        if board.winner.is_none() && !draw {
+            return None;
+        }
+        Some(Game {
+            id: Uuid::new_v4(),
+            variant: board.variant.name().to_string(),
+            winner: board.winner.as_ref().map(|w| w.team.name().to_string()),
+            moves: board.moves,
+            draw,
+            started_at: board.started_at,
+            finished_at: Utc::now()
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct TeamStats {
+    wins: i64,
+    current_streak: i64,
+    longest_streak: i64
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct GameStats {
+    games: i64,
+    draws: i64,
+    milk: TeamStats,
+    cookie: TeamStats,
+    average_moves: f64,
+    average_duration_secs: f64
+}
+impl From<Vec<Game>> for GameStats {
+    /// Expects the games in the order they finished, streaks are counted over consecutive wins.
+    fn from(games: Vec<Game>) -> Self {
+        let mut stats = GameStats::default();
+        let mut total_moves: i64 = 0;
+        let mut total_duration_ms: i64 = 0;
+        for game in &games {
+            stats.games += 1;
+            total_moves += i64::from(game.moves);
+            total_duration_ms += (game.finished_at - game.started_at).num_milliseconds();
+
+            let (winner, loser) = match game.winner.as_deref() {
+                Some("milk") => (&mut stats.milk, &mut stats.cookie),
+                Some("cookie") => (&mut stats.cookie, &mut stats.milk),
+                _ => {
+                    stats.draws += 1;
+                    stats.milk.current_streak = 0;
+                    stats.cookie.current_streak = 0;
+                    continue;
+                }
+            };
+            winner.wins += 1;
+            winner.current_streak += 1;
+            winner.longest_streak = winner.longest_streak.max(winner.current_streak);
+            loser.current_streak = 0;
+        }
+        if stats.games > 0 {
+            stats.average_moves = total_moves as f64 / stats.games as f64;
+            stats.average_duration_secs = total_duration_ms as f64 / 1000.0 / stats.games as f64;
+        }
+        stats
+    }
 }
 
 mod day_12 {
     use std::{mem, sync::Mutex};
 
-    use actix_web::{ get, post, web, HttpResponse};
-    use crate::challenges::day_12::{Board, Team};
+    use actix_web::{ get, http::{header, StatusCode}, post, web, HttpRequest, HttpResponse, HttpResponseBuilder};
+    use serde::Deserialize;
+    use sqlx::PgPool;
+    use crate::challenges::day_12::{Board, BoardResult, Game, GameStats, Move, Team, Variant};
+
+    fn wants_json(req: &HttpRequest) -> bool {
+        req.headers()
+            .get(header::ACCEPT)
+            .and_then(|a| a.to_str().ok())
+            .is_some_and(|a| a.contains("application/json"))
+    }
+
+    fn board_response(state: &Board, status: StatusCode, req: &HttpRequest) -> HttpResponse {
+        if wants_json(req) {
+            return HttpResponseBuilder::new(status).json(BoardResult::from(state));
+        }
+
+        let complement = match &state.winner {
+            Some(w) => format!("{} wins!\n", w.team),
+            None if state.draw() => "No winner.\n".to_string(),
+            None => String::new()
+        };
+        HttpResponseBuilder::new(status).body(format!("{}{}", state.raw_representation, complement))
+    }
 
     #[get("/board")]
-    async fn board(board_state: web::Data<Mutex<Board>>) -> HttpResponse {
+    async fn board(board_state: web::Data<Mutex<Board>>, req: HttpRequest) -> HttpResponse {
         let state = board_state.lock().unwrap();
-        if state.full() {
-            return HttpResponse::Ok().body(format!("{}No winner.\n", state.raw_representation));
+        board_response(&state, StatusCode::OK, &req)
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ResetParams {
+        variant: Option<Variant>
+    }
+    #[post("/reset")]
+    async fn reset(board_state: web::Data<Mutex<Board>>, params: web::Query<ResetParams>, req: HttpRequest) -> HttpResponse {
+        let mut state = board_state.lock().unwrap();
+        let _ = mem::replace(&mut *state, Board::with_variant(params.variant.unwrap_or_default()));
+        board_response(&state, StatusCode::OK, &req)
+    }
+
+    async fn record_game(pgpool: &PgPool, game: Game) {
+        if let Err(e) = sqlx::query("INSERT INTO games (id, variant, winner, moves, draw, started_at, finished_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(game.id)
+            .bind(&game.variant)
+            .bind(&game.winner)
+            .bind(game.moves)
+            .bind(game.draw)
+            .bind(game.started_at)
+            .bind(game.finished_at)
+            .execute(pgpool).await {
+            println!("Error on recording the game: {}", e);
         }
+    }
 
-        let mut complement = String::new();
-        if let Some(w) = &state.winner {
-            complement = format!("{} wins!\n", w);
+    #[derive(Debug, Deserialize)]
+    struct StatsParams {
+        variant: Option<Variant>
+    }
+    #[get("/stats")]
+    async fn stats(pgpool: web::Data<PgPool>, params: web::Query<StatsParams>) -> HttpResponse {
+        match sqlx::query_as::<_, Game>("SELECT * FROM games WHERE $1::TEXT IS NULL OR variant = $1
+            ORDER BY finished_at ASC")
+            .bind(params.variant.map(|v| v.name()))
+            .fetch_all(pgpool.get_ref()).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(games) => HttpResponse::Ok().json(GameStats::from(games))
         }
-        HttpResponse::Ok().body(format!("{}{}", state.raw_representation, complement))
     }
 
-    #[post("/reset")]
-    async fn reset(board_state: web::Data<Mutex<Board>>) -> HttpResponse {
-        let mut state = board_state.lock().unwrap();
-        let _ = mem::replace(&mut *state, Board::new());
-        HttpResponse::Ok().body(state.raw_representation.clone())
+    fn parse_coordinate(value: &str) -> Option<usize> {
+        match value.parse::<u8>() {
+            Ok(c) if c >= 1 => Some(c.into()),
+            _ => None
+        }
     }
 
-    #[post("/place/{team}/{column}")]
-    async fn place(board_state: web::Data<Mutex<Board>>, path: web::Path<(String, String)>) -> HttpResponse {
-        let (team, column) = path.into_inner();
+    async fn play(
+        board_state: &Mutex<Board>,
+        pgpool: &PgPool,
+        team: String,
+        play: Move,
+        req: &HttpRequest
+    ) -> HttpResponse {
         let team = match Team::try_from(team) {
             Ok(t) => t,
             Err(_) => return HttpResponse::BadRequest().finish()
         };
 
-        let column = match column.parse::<u8>() {
-            Err(_) => return HttpResponse::BadRequest().finish(),
-            Ok(c) => {
-                if c < 1 || c > 4 {
-                    return HttpResponse::BadRequest().finish();
-                }
-                c
+        let (response, finished) = {
+            let mut state = board_state.lock().unwrap();
+            let size = state.variant().size();
+            let in_bounds = match play {
+                Move::Drop(c) | Move::Pop(c) => c <= size,
+                Move::Place(c, r) => c <= size && r <= size
+            };
+            if !in_bounds || !state.variant().accepts(&play) {
+                return HttpResponse::BadRequest().finish();
+            }
+
+            if state.winner.is_some() {
+                return board_response(&state, StatusCode::SERVICE_UNAVAILABLE, req);
+            }
+            if state.draw() {
+                return board_response(&state, StatusCode::OK, req);
+            }
+
+            if state.play(&team, play).is_err() {
+                return HttpResponse::ServiceUnavailable().finish();
             }
+            state.grid_update();
+            state.winner = state.winner();
+
+            (board_response(&state, StatusCode::OK, req), Game::finished(&state))
         };
-        let mut state = board_state.lock().unwrap();
-        if let Some(w) = &state.winner {
-            return HttpResponse::ServiceUnavailable().body(format!("{}{} wins!\n", state.raw_representation, w));
-        }
-        if state.full() {
-            return HttpResponse::Ok().body(format!("{}No winner.\n", state.raw_representation));
+
+        if let Some(game) = finished {
+            record_game(pgpool, game).await;
         }
+        response
+    }
 
-        if let Err(e) = state.set_position(&team, column.into()) {
-            return HttpResponse::ServiceUnavailable().finish();
+    #[post("/place/{team}/{column}")]
+    async fn place(
+        board_state: web::Data<Mutex<Board>>,
+        pgpool: web::Data<PgPool>,
+        path: web::Path<(String, String)>,
+        req: HttpRequest
+    ) -> HttpResponse {
+        let (team, column) = path.into_inner();
+        match parse_coordinate(&column) {
+            None => HttpResponse::BadRequest().finish(),
+            Some(c) => play(&board_state, &pgpool, team, Move::Drop(c), &req).await
         }
-        state.grid_update();
+    }
 
-        let mut complement: String = String::new();
-        if state.winner() == true {
-            complement = format!("{} wins!\n", team);
-            state.winner = Some(team);
-        } else if state.full() {
-            return HttpResponse::Ok().body(format!("{}No winner.\n", state.raw_representation));
+    #[post("/place/{team}/{column}/{row}")]
+    async fn place_at(
+        board_state: web::Data<Mutex<Board>>,
+        pgpool: web::Data<PgPool>,
+        path: web::Path<(String, String, String)>,
+        req: HttpRequest
+    ) -> HttpResponse {
+        let (team, column, row) = path.into_inner();
+        match (parse_coordinate(&column), parse_coordinate(&row)) {
+            (Some(c), Some(r)) => play(&board_state, &pgpool, team, Move::Place(c, r), &req).await,
+            _ => HttpResponse::BadRequest().finish()
         }
+    }
 
-        HttpResponse::Ok().body(format!("{}{}", state.raw_representation, complement))
+    #[post("/pop/{team}/{column}")]
+    async fn pop(
+        board_state: web::Data<Mutex<Board>>,
+        pgpool: web::Data<PgPool>,
+        path: web::Path<(String, String)>,
+        req: HttpRequest
+    ) -> HttpResponse {
+        let (team, column) = path.into_inner();
+        match parse_coordinate(&column) {
+            None => HttpResponse::BadRequest().finish(),
+            Some(c) => play(&board_state, &pgpool, team, Move::Pop(c), &req).await
+        }
     }
 }
 
@@ -237,4 +587,118 @@ pub fn scope() -> Scope {
         .service(day_12::board)
         .service(day_12::reset)
         .service(day_12::place)
+        .service(day_12::place_at)
+        .service(day_12::pop)
+        .service(day_12::stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(variant: Variant, moves: &[(Team, Move)]) -> Board {
+        let mut board = Board::with_variant(variant);
+        for (team, play) in moves {
+            board.play(team, *play).unwrap();
+        }
+        board
+    }
+
+    fn cells(cells: &[(usize, usize)]) -> Vec<Cell> {
+        cells.iter().map(|&(column, row)| Cell::new(column, row)).collect()
+    }
+
+    #[test]
+    fn empty_board_has_no_winner() {
+        assert!(Board::new().winner().is_none());
+    }
+
+    #[test]
+    fn winner_reports_column() {
+        let win = board(Variant::ConnectFour, &[(Team::Cookie, Move::Drop(2)); 4]).winner().unwrap();
+        assert_eq!(win.team, Team::Cookie);
+        assert!(matches!(win.direction, Direction::Column));
+        assert_eq!(win.cells, cells(&[(1, 0), (1, 1), (1, 2), (1, 3)]));
+    }
+
+    #[test]
+    fn winner_reports_row() {
+        let moves = (1..=4).map(|c| (Team::Milk, Move::Drop(c))).collect::<Vec<_>>();
+        let win = board(Variant::ConnectFour, &moves).winner().unwrap();
+        assert_eq!(win.team, Team::Milk);
+        assert!(matches!(win.direction, Direction::Row));
+        assert_eq!(win.cells, cells(&[(0, 0), (1, 0), (2, 0), (3, 0)]));
+    }
+
+    #[test]
+    fn winner_reports_diagonal() {
+        let mut moves = vec![];
+        for c in 1..=4 {
+            moves.extend((1..c).map(|_| (Team::Milk, Move::Drop(c))));
+            moves.push((Team::Cookie, Move::Drop(c)));
+        }
+        let win = board(Variant::ConnectFour, &moves).winner().unwrap();
+        assert_eq!(win.team, Team::Cookie);
+        assert!(matches!(win.direction, Direction::Diagonal));
+        assert_eq!(win.cells, cells(&[(0, 0), (1, 1), (2, 2), (3, 3)]));
+    }
+
+    #[test]
+    fn gravity_free_places_anywhere() {
+        let moves = (1..=4).map(|i| (Team::Milk, Move::Place(i, 5 - i))).collect::<Vec<_>>();
+        let win = board(Variant::GravityFree, &moves).winner().unwrap();
+        assert_eq!(win.team, Team::Milk);
+        assert!(matches!(win.direction, Direction::Diagonal));
+        assert_eq!(win.cells, cells(&[(0, 3), (1, 2), (2, 1), (3, 0)]));
+    }
+
+    #[test]
+    fn tic_tac_toe_wins_on_three() {
+        let moves = (1..=3).map(|c| (Team::Cookie, Move::Place(c, 3))).collect::<Vec<_>>();
+        let win = board(Variant::TicTacToe, &moves).winner().unwrap();
+        assert_eq!(win.team, Team::Cookie);
+        assert!(matches!(win.direction, Direction::Row));
+        assert_eq!(win.cells, cells(&[(0, 2), (1, 2), (2, 2)]));
+    }
+
+    #[test]
+    fn pop_out_completing_both_teams_lines_goes_to_the_popper() {
+        // Popping the bottom cookie of column 1 completes the cookie row 2 and the milk row 3
+        // at once, cookie wins although the milk line is found last.
+        let columns = [
+            vec![Team::Cookie, Team::Milk, Team::Cookie, Team::Milk],
+            vec![Team::Cookie, Team::Cookie, Team::Milk],
+            vec![Team::Milk, Team::Cookie, Team::Milk],
+            vec![Team::Milk, Team::Cookie, Team::Milk]
+        ];
+        let moves = columns.iter().enumerate()
+            .flat_map(|(c, teams)| teams.iter().map(move |team| (*team, Move::Drop(c + 1))))
+            .collect::<Vec<_>>();
+        let mut board = board(Variant::PopOut, &moves);
+        assert!(board.winner().is_none());
+
+        board.play(&Team::Cookie, Move::Pop(1)).unwrap();
+        let win = board.winner().unwrap();
+        assert_eq!(win.team, Team::Cookie);
+        assert!(matches!(win.direction, Direction::Row));
+        assert_eq!(win.cells, cells(&[(0, 1), (1, 1), (2, 1), (3, 1)]));
+    }
+
+    #[test]
+    fn pop_out_only_pops_own_pieces() {
+        let mut board = board(Variant::PopOut, &[(Team::Cookie, Move::Drop(1))]);
+        assert!(board.play(&Team::Milk, Move::Pop(1)).is_err());
+    }
+
+    #[test]
+    fn game_starts_on_the_first_move() {
+        let mut board = Board::new();
+        let before_first_move = Utc::now();
+        board.play(&Team::Cookie, Move::Drop(1)).unwrap();
+        let started_at = board.started_at;
+        assert!(started_at >= before_first_move);
+
+        board.play(&Team::Milk, Move::Drop(2)).unwrap();
+        assert_eq!(board.started_at, started_at);
+    }
 }
@@ -1,69 +1,995 @@
-use actix_web::{web, Scope};
+use std::{collections::HashMap, error::Error, sync::RwLock};
+
+use actix_web::{cookie::SameSite, web, Scope};
+use aes_gcm::{aead::{Aead, AeadCore, KeyInit, OsRng, Payload}, Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonschema::Validator;
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, EllipticCurveKeyType,
+        Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+        RSAKeyParameters, RSAKeyType
+    },
+    decode, decode_header,
+    errors::{Error as JwtError, ErrorKind},
+    get_current_timestamp, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation
+};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+    pkcs8::{
+        der::{pem, Decode},
+        spki::{ObjectIdentifier, SubjectPublicKeyInfoRef},
+        DecodePrivateKey, EncodePrivateKey, LineEnding
+    },
+    traits::PublicKeyParts, RsaPrivateKey, RsaPublicKey
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+/// Registered claims injected on `/16/wrap` and enforced on `/16/unwrap`.
+/// Loaded from the secrets, `ttl` and `issuer` can be overridden per request.
+#[derive(Debug, Clone, Default)]
+pub struct GiftConfig {
+    pub ttl: Option<u64>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub cookie: GiftCookie,
+    /// `/16/unwrap` re-issues gifts expiring within this many seconds.
+    pub refresh_within: Option<u64>
+}
+
+/// Attributes of the `gift` cookie. Without `max_age` the cookie lives as long as the gift.
+#[derive(Debug, Clone)]
+pub struct GiftCookie {
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub path: String,
+    pub max_age: Option<u64>
+}
+impl Default for GiftCookie {
+    fn default() -> Self {
+        GiftCookie {
+            http_only: true,
+            secure: true,
+            same_site: SameSite::Strict,
+            path: "/16".to_string(),
+            max_age: None
+        }
+    }
+}
+
+/// Gifts above this size are refused when no `GiftSchema` sets another limit.
+pub const MAX_GIFT_BYTES: usize = 16 * 1024;
+
+/// Shape `/16/wrap` requires from the gifts before signing them. Registered as app data,
+/// so it can be attached to the whole scope or to a single route.
+pub struct GiftSchema {
+    validator: Option<Validator>,
+    pub max_bytes: usize
+}
+impl GiftSchema {
+    pub fn new(schema: Option<&str>, max_bytes: usize) -> Result<Self, Box<dyn Error>> {
+        let validator = match schema {
+            None => None,
+            Some(schema) => Some(
+                jsonschema::validator_for(&serde_json::from_str(schema)?).map_err(|e| e.to_string())?
+            )
+        };
+        Ok(GiftSchema { validator, max_bytes })
+    }
+
+    /// Every schema violation of the gift as `(JSON pointer, message)`.
+    pub fn violations(&self, gift: &serde_json::Value) -> Vec<(String, String)> {
+        match &self.validator {
+            None => vec![],
+            Some(validator) => validator
+                .iter_errors(gift)
+                .map(|e| (e.instance_path.to_string(), e.to_string()))
+                .collect()
+        }
+    }
+}
+
+/// `jti` of the revoked gifts along with their `exp`, written through to the `revoked_gifts` table
+/// when persistent. The table is loaded on startup and looked up on a cache miss, so revocations
+/// survive restarts and are shared by every instance.
+pub struct Revocations {
+    revoked: RwLock<HashMap<String, Option<u64>>>,
+    pgpool: Option<PgPool>
+}
+impl Revocations {
+    pub fn in_memory() -> Self {
+        Revocations { revoked: RwLock::new(HashMap::new()), pgpool: None }
+    }
+
+    /// Loads the revocations still relevant, the ones of already expired gifts are dropped.
+    pub async fn persistent(pgpool: PgPool) -> Result<Self, sqlx::Error> {
+        sqlx::query("DELETE FROM revoked_gifts WHERE expires_at < CURRENT_TIMESTAMP")
+            .execute(&pgpool).await?;
+        let revoked = sqlx::query_as::<_, (String, Option<f64>)>("SELECT jti, EXTRACT(EPOCH FROM expires_at)::FLOAT8 FROM revoked_gifts")
+            .fetch_all(&pgpool).await?;
+        let revoked = revoked.into_iter().map(|(jti, expires_at)| (jti, expires_at.map(|exp| exp as u64))).collect();
+        Ok(Revocations { revoked: RwLock::new(revoked), pgpool: Some(pgpool) })
+    }
+
+    /// `expires_at` is the gift's `exp`, past it the revocation can be forgotten.
+    pub async fn revoke(&self, jti: &str, expires_at: Option<u64>) -> Result<(), sqlx::Error> {
+        if let Some(pgpool) = &self.pgpool {
+            sqlx::query("DELETE FROM revoked_gifts WHERE expires_at < CURRENT_TIMESTAMP")
+                .execute(pgpool).await?;
+            sqlx::query("INSERT INTO revoked_gifts (jti, expires_at) VALUES ($1, to_timestamp($2))
+                ON CONFLICT (jti) DO NOTHING")
+                .bind(jti)
+                .bind(expires_at.map(|exp| exp as f64))
+                .execute(pgpool).await?;
+        }
+        self.remember(jti, expires_at);
+        Ok(())
+    }
+
+    /// Looks the table up when the `jti` isn't cached, another instance may have revoked it.
+    pub async fn is_revoked(&self, jti: &str) -> Result<bool, sqlx::Error> {
+        if self.revoked.read().unwrap().contains_key(jti) {
+            return Ok(true);
+        }
+        let Some(pgpool) = &self.pgpool else {
+            return Ok(false);
+        };
+        let revocation = sqlx::query_scalar::<_, Option<f64>>("SELECT EXTRACT(EPOCH FROM expires_at)::FLOAT8 FROM revoked_gifts
+            WHERE jti = $1 AND (expires_at IS NULL OR expires_at >= CURRENT_TIMESTAMP)")
+            .bind(jti)
+            .fetch_optional(pgpool).await?;
+        match revocation {
+            None => Ok(false),
+            Some(expires_at) => {
+                self.remember(jti, expires_at.map(|exp| exp as u64));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Caches a revocation, forgetting the ones of the gifts expired since.
+    fn remember(&self, jti: &str, expires_at: Option<u64>) {
+        let now = get_current_timestamp();
+        let mut revoked = self.revoked.write().unwrap();
+        revoked.retain(|_, exp| exp.is_none_or(|exp| exp >= now));
+        revoked.insert(jti.to_string(), expires_at);
+    }
+}
+
+/// Protected header of the encrypted gifts, the payload is the signed gift itself.
+const JWE_HEADER: &str = r#"{"alg":"dir","enc":"A256GCM","cty":"JWT"}"#;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum KeyFamily {
+    #[serde(rename = "oct")]
+    Hmac,
+    #[serde(rename = "RSA")]
+    Rsa,
+    #[serde(rename = "EC")]
+    Ec,
+    #[serde(rename = "OKP")]
+    Ed
+}
+impl From<Algorithm> for KeyFamily {
+    fn from(alg: Algorithm) -> Self {
+        match alg {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => KeyFamily::Hmac,
+            Algorithm::ES256 | Algorithm::ES384 => KeyFamily::Ec,
+            Algorithm::EdDSA => KeyFamily::Ed,
+            _ => KeyFamily::Rsa
+        }
+    }
+}
+
+const RSA_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const EC_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const P256_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const P384_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.34");
+const ED25519_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+/// Public half of a gift key, published on the JWKS endpoint under its `kid`,
+/// the RFC 7638 thumbprint unless the imported JWK brought its own.
+#[derive(Clone)]
+pub struct GiftKey {
+    family: KeyFamily,
+    jwk: Jwk,
+    decoding: DecodingKey
+}
+impl GiftKey {
+    fn from_jwk(mut jwk: Jwk) -> Result<Self, Box<dyn Error>> {
+        let (family, key_algorithm, canonical) = match &jwk.algorithm {
+            AlgorithmParameters::RSA(rsa) => (
+                KeyFamily::Rsa,
+                KeyAlgorithm::RS256,
+                format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, rsa.e, rsa.n)
+            ),
+            AlgorithmParameters::EllipticCurve(ec) => {
+                let (key_algorithm, crv) = match ec.curve {
+                    EllipticCurve::P256 => (KeyAlgorithm::ES256, "P-256"),
+                    EllipticCurve::P384 => (KeyAlgorithm::ES384, "P-384"),
+                    _ => return Err(From::from("Unsupported curve"))
+                };
+                (KeyFamily::Ec, key_algorithm, format!(r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#, crv, ec.x, ec.y))
+            },
+            AlgorithmParameters::OctetKeyPair(okp) if okp.curve == EllipticCurve::Ed25519 => (
+                KeyFamily::Ed,
+                KeyAlgorithm::EdDSA,
+                format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, okp.x)
+            ),
+            _ => return Err(From::from("Unsupported key type"))
+        };
+        jwk.common.public_key_use.get_or_insert(PublicKeyUse::Signature);
+        jwk.common.key_algorithm.get_or_insert(key_algorithm);
+        jwk.common.key_id.get_or_insert_with(|| URL_SAFE_NO_PAD.encode(Sha256::digest(canonical)));
+
+        let decoding = DecodingKey::from_jwk(&jwk)?;
+        Ok(GiftKey { family, jwk, decoding })
+    }
+
+    fn from_parameters(algorithm: AlgorithmParameters) -> Result<Self, Box<dyn Error>> {
+        GiftKey::from_jwk(Jwk { common: CommonParameters::default(), algorithm })
+    }
+
+    fn from_rsa(key: &RsaPublicKey) -> Result<Self, Box<dyn Error>> {
+        GiftKey::from_parameters(AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be())
+        }))
+    }
+
+    /// `point` is an uncompressed SEC1 point, `0x04 || x || y`.
+    fn from_ec_point(curve: EllipticCurve, point: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let Some((0x04, coordinates)) = point.split_first() else {
+            return Err(From::from("Only uncompressed EC points are supported"));
+        };
+        let (x, y) = coordinates.split_at(coordinates.len() / 2);
+        GiftKey::from_parameters(AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+            key_type: EllipticCurveKeyType::EC,
+            curve,
+            x: URL_SAFE_NO_PAD.encode(x),
+            y: URL_SAFE_NO_PAD.encode(y)
+        }))
+    }
+
+    fn from_ed(key: &[u8]) -> Result<Self, Box<dyn Error>> {
+        GiftKey::from_parameters(AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: URL_SAFE_NO_PAD.encode(key)
+        }))
+    }
+
+    /// Accepts PKCS#1 RSA keys and SubjectPublicKeyInfo RSA, P-256, P-384 and Ed25519 keys.
+    fn from_public_pem(pem: &str) -> Result<Self, Box<dyn Error>> {
+        let (label, der) = pem::decode_vec(pem.as_bytes()).map_err(|_| "Malformed PEM")?;
+        if label == "RSA PUBLIC KEY" {
+            return GiftKey::from_rsa(&RsaPublicKey::from_pkcs1_der(&der)?);
+        }
+
+        let spki = SubjectPublicKeyInfoRef::from_der(&der)?;
+        let raw_key = spki.subject_public_key.raw_bytes();
+        match spki.algorithm.oid {
+            RSA_OID => GiftKey::from_rsa(&RsaPublicKey::try_from(spki)?),
+            ED25519_OID => GiftKey::from_ed(raw_key),
+            EC_OID => match spki.algorithm.parameters_oid()? {
+                P256_OID => GiftKey::from_ec_point(EllipticCurve::P256, raw_key),
+                P384_OID => GiftKey::from_ec_point(EllipticCurve::P384, raw_key),
+                _ => Err(From::from("Unsupported curve"))
+            },
+            _ => Err(From::from("Unsupported key type"))
+        }
+    }
+
+    pub fn kid(&self) -> &str {
+        self.jwk.common.key_id.as_deref().unwrap_or_default()
+    }
+}
+
+struct SigningKey {
+    kid: String,
+    family: KeyFamily,
+    encoding: EncodingKey
+}
+impl SigningKey {
+    /// Parses a PKCS#8, PKCS#1 or SEC1 private key along with its public half.
+    fn from_private_pem(pem: &str) -> Result<(Self, GiftKey), Box<dyn Error>> {
+        if let Ok(key) = RsaPrivateKey::from_pkcs8_pem(pem).or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem)) {
+            let public = GiftKey::from_rsa(&key.to_public_key())?;
+            let encoding = EncodingKey::from_rsa_pem(key.to_pkcs8_pem(LineEnding::LF)?.as_bytes())?;
+            return Ok((SigningKey { kid: public.kid().to_string(), family: KeyFamily::Rsa, encoding }, public));
+        }
+        let key = p256::SecretKey::from_pkcs8_pem(pem).or_else(|_| p256::SecretKey::from_sec1_pem(pem))?;
+        SigningKey::from_ec(&key)
+    }
+
+    fn from_ec(key: &p256::SecretKey) -> Result<(Self, GiftKey), Box<dyn Error>> {
+        let public = GiftKey::from_ec_point(EllipticCurve::P256, key.public_key().to_encoded_point(false).as_bytes())?;
+        let encoding = EncodingKey::from_ec_pem(key.to_pkcs8_pem(LineEnding::LF)?.as_bytes())?;
+        Ok((SigningKey { kid: public.kid().to_string(), family: KeyFamily::Ec, encoding }, public))
+    }
+
+    fn generate(family: KeyFamily) -> Result<(Self, GiftKey), Box<dyn Error>> {
+        match family {
+            KeyFamily::Hmac => Err(From::from("HMAC keys are not rotated")),
+            KeyFamily::Ed => Err(From::from("Ed25519 keys can only be imported")),
+            KeyFamily::Ec => SigningKey::from_ec(&p256::SecretKey::random(&mut rand::thread_rng())),
+            KeyFamily::Rsa => {
+                let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048)?;
+                SigningKey::from_private_pem(&key.to_pkcs8_pem(LineEnding::LF)?)
+            }
+        }
+    }
+}
+
+/// Key material for the gifts: the HMAC secret, the current RSA/EC signing key and every
+/// public key still accepted, so gifts signed before a rotation keep unwrapping as long as
+/// the process lives.
+pub struct GiftKeys {
+    hmac_secret: Vec<u8>,
+    encryption_key: Option<Key<Aes256Gcm>>,
+    signing: RwLock<Option<SigningKey>>,
+    verifying: RwLock<Vec<GiftKey>>
+}
+impl GiftKeys {
+    /// `public_pems` may hold several PEM blocks one after the other.
+    pub fn from_pems(hmac_secret: &[u8], private_pem: Option<&str>, public_pems: &str) -> Result<Self, Box<dyn Error>> {
+        let keys = GiftKeys {
+            hmac_secret: hmac_secret.to_vec(),
+            encryption_key: None,
+            signing: RwLock::new(None),
+            verifying: RwLock::new(vec![])
+        };
+        for pem in split_pems(public_pems) {
+            keys.add(GiftKey::from_public_pem(pem)?);
+        }
+        if let Some(pem) = private_pem {
+            let (signing, public) = SigningKey::from_private_pem(pem)?;
+            keys.add(public);
+            *keys.signing.write().unwrap() = Some(signing);
+        }
+        Ok(keys)
+    }
+
+    /// Adds verification keys given as a JWK Set, keeping their `kid` when they have one.
+    pub fn with_jwks(self, jwks: &str) -> Result<Self, Box<dyn Error>> {
+        for jwk in serde_json::from_str::<JwkSet>(jwks)?.keys {
+            self.add(GiftKey::from_jwk(jwk)?);
+        }
+        Ok(self)
+    }
+
+    /// Enables encrypted gifts, `key` is the base64url encoded 256 bits `dir` key.
+    pub fn with_encryption_key(mut self, key: &str) -> Result<Self, Box<dyn Error>> {
+        let key = URL_SAFE_NO_PAD.decode(key.trim())?;
+        if key.len() != 32 {
+            return Err(From::from("Encryption key must be 256 bits"));
+        }
+        self.encryption_key = Some(*Key::<Aes256Gcm>::from_slice(&key));
+        Ok(self)
+    }
+
+    /// Wraps a signed gift in a compact JWE using direct encryption with A256GCM.
+    fn encrypt(&self, token: &str) -> Result<String, Box<dyn Error>> {
+        let key = self.encryption_key.as_ref().ok_or("No encryption key configured")?;
+        let protected = URL_SAFE_NO_PAD.encode(JWE_HEADER);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = Aes256Gcm::new(key)
+            .encrypt(&nonce, Payload { msg: token.as_bytes(), aad: protected.as_bytes() })
+            .map_err(|_| "Unable to encrypt the gift")?;
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - 16);
+
+        Ok(format!(
+            "{}..{}.{}.{}",
+            protected,
+            URL_SAFE_NO_PAD.encode(nonce),
+            URL_SAFE_NO_PAD.encode(ciphertext),
+            URL_SAFE_NO_PAD.encode(tag)
+        ))
+    }
+
+    /// Opens a compact JWE produced by `encrypt`, returning the signed gift inside.
+    fn decrypt(&self, token: &str) -> Result<String, Box<dyn Error>> {
+        let key = self.encryption_key.as_ref().ok_or("No encryption key configured")?;
+        let [protected, encrypted_key, iv, ciphertext, tag] = token.split('.').collect::<Vec<&str>>()[..] else {
+            return Err(From::from("Malformed encrypted gift"));
+        };
+        let header: HashMap<String, String> = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(protected)?)?;
+        if !encrypted_key.is_empty()
+            || header.get("alg").map(String::as_str) != Some("dir")
+            || header.get("enc").map(String::as_str) != Some("A256GCM")
+        {
+            return Err(From::from("Unsupported encryption"));
+        }
+
+        let iv = URL_SAFE_NO_PAD.decode(iv)?;
+        if iv.len() != 12 {
+            return Err(From::from("Malformed encrypted gift"));
+        }
+        let mut sealed = URL_SAFE_NO_PAD.decode(ciphertext)?;
+        sealed.extend(URL_SAFE_NO_PAD.decode(tag)?);
+        let token = Aes256Gcm::new(key)
+            .decrypt(Nonce::from_slice(&iv), Payload { msg: &sealed, aad: protected.as_bytes() })
+            .map_err(|_| "Unable to decrypt the gift")?;
+        Ok(String::from_utf8(token)?)
+    }
+
+    fn add(&self, key: GiftKey) {
+        let mut verifying = self.verifying.write().unwrap();
+        if !verifying.iter().any(|k| k.kid() == key.kid()) {
+            verifying.push(key);
+        }
+    }
+
+    /// Signs new gifts with a freshly generated key, previous keys stay valid for verification.
+    /// The generated key is only kept in memory: after a restart, or on another instance, gifts
+    /// signed with it no longer verify. Keys meant to last go in `GIFT_PRIVATE_PEM` and `PUB_PEM`.
+    pub fn rotate(&self, family: Option<KeyFamily>) -> Result<GiftKey, Box<dyn Error>> {
+        let current = self.signing.read().unwrap().as_ref().map(|k| k.family);
+        let (signing, public) = SigningKey::generate(family.or(current).unwrap_or(KeyFamily::Ec))?;
+        self.add(public.clone());
+        *self.signing.write().unwrap() = Some(signing);
+        Ok(public)
+    }
+
+    /// Stops accepting gifts signed with `kid`, the current signing key can't be retired.
+    /// `false` when there's no key with this `kid`.
+    pub fn retire(&self, kid: &str) -> Result<bool, Box<dyn Error>> {
+        if self.signing.read().unwrap().as_ref().is_some_and(|k| k.kid == kid) {
+            return Err(From::from("Key in use for signing"));
+        }
+        let mut verifying = self.verifying.write().unwrap();
+        let before = verifying.len();
+        verifying.retain(|k| k.kid() != kid);
+        Ok(verifying.len() < before)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet { keys: self.verifying.read().unwrap().iter().map(|k| k.jwk.clone()).collect() }
+    }
+
+    /// Key for signing with `alg` and the `kid` to put in the header.
+    fn encoding_key(&self, alg: Algorithm) -> Option<(Option<String>, EncodingKey)> {
+        match (KeyFamily::from(alg), self.signing.read().unwrap().as_ref()) {
+            (KeyFamily::Hmac, _) => Some((None, EncodingKey::from_secret(&self.hmac_secret))),
+            (family, Some(signing)) if family == signing.family => Some((Some(signing.kid.clone()), signing.encoding.clone())),
+            _ => None
+        }
+    }
+
+    /// Tries every key of the token's algorithm family, a key that passes the signature
+    /// check but fails the claims validation ends the search with that error.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str, validation: &Validation) -> Result<TokenData<T>, JwtError> {
+        let header = decode_header(token)?;
+        if !validation.algorithms.contains(&header.alg) {
+            return Err(JwtError::from(ErrorKind::InvalidAlgorithm));
+        }
+        // The keys only accept algorithms of their own family, so narrow it down to the token's one.
+        let mut validation = validation.clone();
+        validation.algorithms = vec![header.alg];
+
+        let mut result = Err(JwtError::from(ErrorKind::InvalidSignature));
+        for key in self.decoding_keys(&header) {
+            result = decode::<T>(token, &key, &validation);
+            match &result {
+                Err(e) if matches!(e.kind(), ErrorKind::InvalidSignature | ErrorKind::InvalidKeyFormat) => continue,
+                _ => break
+            }
+        }
+        result
+    }
+
+    /// Keys to verify a token with the given header, narrowed down to its `kid` when present.
+    fn decoding_keys(&self, header: &Header) -> Vec<DecodingKey> {
+        match KeyFamily::from(header.alg) {
+            KeyFamily::Hmac => vec![DecodingKey::from_secret(&self.hmac_secret)],
+            family => self.verifying
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|k| k.family == family && header.kid.as_deref().is_none_or(|kid| kid == k.kid()))
+                .map(|k| k.decoding.clone())
+                .collect()
+        }
+    }
+}
+
+/// Splits concatenated PEM blocks, anything outside `-----BEGIN`/`-----END` is ignored.
+fn split_pems(pems: &str) -> Vec<&str> {
+    let mut blocks = vec![];
+    let mut rest = pems;
+    while let Some(start) = rest.find("-----BEGIN") {
+        let Some(end) = rest[start..].find("-----END").map(|e| start + e + "-----END".len()) else {
+            break;
+        };
+        let close = rest[end..].find("-----").map_or(rest.len(), |c| end + c + "-----".len());
+        blocks.push(&rest[start..close]);
+        rest = &rest[close..];
+    }
+    blocks
+}
 
 mod gift {
     use std::collections::{HashMap, HashSet};
 
-    use actix_web::{cookie::Cookie, get, post, web, HttpRequest, HttpResponse};
-    use jsonwebtoken::{decode, encode, errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+    use actix_web::{cookie::{time::Duration, Cookie}, delete, get, http::StatusCode, post, web, HttpRequest, HttpResponse};
+    use jsonwebtoken::{decode_header, encode, errors::ErrorKind, get_current_timestamp, Algorithm, Header, Validation};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use serde::{Deserialize, Serialize};
     use serde_json::Value;
+    use sqlx::types::Uuid;
+    use crate::{
+        challenges::day_16::{GiftConfig, GiftKeys, GiftSchema, KeyFamily, Revocations, MAX_GIFT_BYTES},
+        problem::Problem
+    };
 
     type Gift = HashMap<String, Value>;
 
+    async fn revoked(revocations: &Revocations, gift: &Gift) -> Result<bool, sqlx::Error> {
+        match gift.get("jti").and_then(Value::as_str) {
+            None => Ok(false),
+            Some(jti) => revocations.is_revoked(jti).await
+        }
+    }
+
+    fn revocation_failure(e: sqlx::Error) -> HttpResponse {
+        println!("Error on checking the revoked gifts: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+
+    /// Time and audience claims are only checked when the gift carries them,
+    /// plain gifts without any registered claim keep unwrapping.
+    fn gift_validation(alg: Algorithm, config: &GiftConfig) -> Validation {
+        let mut validation = Validation::new(alg);
+        validation.leeway = 0;
+        validation.validate_nbf = true;
+        validation.required_spec_claims = HashSet::new();
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        // Without an audience of our own, a gift's `aud` is just another claim.
+        validation.validate_aud = config.audience.is_some();
+        if let Some(audience) = &config.audience {
+            validation.set_audience(&[audience]);
+        }
+        validation
+    }
+
+    /// Only checks the signature, the claims of the gift are not validated.
+    fn signature_validation(alg: Algorithm) -> Validation {
+        let mut validation = Validation::new(alg);
+        validation.validate_exp = false;
+        validation.validate_aud = false;
+        validation.required_spec_claims = HashSet::new();
+        validation
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct WrapParams {
+        alg: Option<Algorithm>,
+        #[serde(default)]
+        encrypt: bool,
+        ttl: Option<u64>,
+        delay: Option<u64>,
+        iss: Option<String>
+    }
+
+    /// Reason sent back with the 401 when a gift fails the registered claims validation.
+    fn claim_rejection(kind: &ErrorKind) -> Option<&'static str> {
+        match kind {
+            ErrorKind::ExpiredSignature => Some("Gift expired.\n"),
+            ErrorKind::ImmatureSignature => Some("Gift not yet valid.\n"),
+            ErrorKind::InvalidAudience => Some("Gift for the wrong audience.\n"),
+            ErrorKind::InvalidIssuer => Some("Gift from an unknown issuer.\n"),
+            ErrorKind::InvalidSignature => Some("Gift signature is invalid.\n"),
+            _ => None
+        }
+    }
+
+    /// Gift with a pushed back `exp` when it expires within `refresh_within`, keeping its
+    /// original lifetime and `jti` so a revocation still covers the refreshed gift.
+    fn refresh(gift: &Gift, config: &GiftConfig) -> Option<Gift> {
+        let refresh_within = config.refresh_within?;
+        let exp = gift.get("exp").and_then(Value::as_u64)?;
+        let now = get_current_timestamp();
+        if exp.saturating_sub(now) > refresh_within {
+            return None;
+        }
+
+        let ttl = gift.get("iat").and_then(Value::as_u64).map_or(refresh_within, |iat| exp.saturating_sub(iat));
+        let mut refreshed = gift.clone();
+        refreshed.insert("iat".to_string(), now.into());
+        refreshed.insert("exp".to_string(), now.saturating_add(ttl).into());
+        Some(refreshed)
+    }
+
+    /// Signs the gift with `alg`, encrypts it when asked, and puts it in the `gift` cookie.
+    fn gift_cookie(
+        keys: &GiftKeys,
+        config: &GiftConfig,
+        alg: Algorithm,
+        gift: &Gift,
+        encrypt: bool
+    ) -> Result<Cookie<'static>, Box<dyn std::error::Error>> {
+        let (kid, encoding_key) = keys.encoding_key(alg)
+            .ok_or_else(|| format!("No signing key configured for {:?}", alg))?;
+        let mut header = Header::new(alg);
+        header.kid = kid;
+        let mut token = encode(&header, gift, &encoding_key)?;
+        if encrypt {
+            token = keys.encrypt(&token)?;
+        }
+
+        let mut cookie = Cookie::build("gift", token)
+            .http_only(config.cookie.http_only)
+            .secure(config.cookie.secure)
+            .same_site(config.cookie.same_site)
+            .path(config.cookie.path.clone())
+            .finish();
+        let expires_in = gift.get("exp")
+            .and_then(Value::as_u64)
+            .map(|exp| exp.saturating_sub(get_current_timestamp()));
+        if let Some(max_age) = config.cookie.max_age.or(expires_in) {
+            cookie.set_max_age(Duration::seconds(max_age as i64));
+        }
+        Ok(cookie)
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Violation {
+        path: String,
+        message: String
+    }
+
+    /// Parses the gift to wrap, refusing oversized, malformed or schema violating ones.
+    fn parse_gift(bytes: &[u8], schema: Option<&GiftSchema>) -> Result<Gift, Problem> {
+        let max_bytes = schema.map_or(MAX_GIFT_BYTES, |s| s.max_bytes);
+        if bytes.len() > max_bytes {
+            return Err(Problem::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Gift too large",
+                format!("The gift is {} bytes, the limit is {} bytes.", bytes.len(), max_bytes)
+            ));
+        }
+
+        let gift: Value = serde_json::from_slice(bytes)
+            .map_err(|e| Problem::new(StatusCode::BAD_REQUEST, "Malformed gift", e))?;
+        let violations = schema.map(|s| s.violations(&gift)).unwrap_or_default();
+        if !violations.is_empty() {
+            let violations: Vec<_> = violations.into_iter().map(|(path, message)| Violation { path, message }).collect();
+            return Err(Problem::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Invalid gift",
+                format!("The gift breaks {} rule(s) of the gift schema.", violations.len())
+            ).with("violations", violations));
+        }
+
+        match gift {
+            Value::Object(gift) => Ok(gift.into_iter().collect()),
+            _ => Err(Problem::new(StatusCode::BAD_REQUEST, "Malformed gift", "The gift must be a JSON object."))
+        }
+    }
+
     #[post("/wrap")]
-    async fn pack(jwt_secret: web::Data<Vec<u8>>, bytes: web::Bytes) -> HttpResponse {
-        let encoding_key = EncodingKey::from_secret(jwt_secret.as_slice());
-        let json_gift: Gift = serde_json::from_slice::<Gift>(&bytes)
-            .expect("Unable to parse the gift on pack.");
+    async fn pack(
+        keys: web::Data<GiftKeys>,
+        config: web::Data<GiftConfig>,
+        schema: Option<web::Data<GiftSchema>>,
+        params: web::Query<WrapParams>,
+        bytes: web::Bytes
+    ) -> HttpResponse {
+        let alg = params.alg.unwrap_or_default();
+        if keys.encoding_key(alg).is_none() {
+            return HttpResponse::BadRequest().body(format!("No signing key configured for {:?}.\n", alg));
+        }
+        if params.encrypt && keys.encryption_key.is_none() {
+            return HttpResponse::BadRequest().body("No encryption key configured.\n");
+        }
+        let mut json_gift = match parse_gift(&bytes, schema.as_ref().map(|s| s.get_ref())) {
+            Err(problem) => return problem.response(),
+            Ok(gift) => gift
+        };
 
-        match encode(&Header::default(), &json_gift, &encoding_key) {
+        let now = get_current_timestamp();
+        if let Some(ttl) = params.ttl.or(config.ttl) {
+            let Some(exp) = now.checked_add(ttl) else {
+                return HttpResponse::BadRequest().body("ttl is too large.\n");
+            };
+            json_gift.insert("iat".to_string(), now.into());
+            json_gift.insert("exp".to_string(), exp.into());
+        }
+        if let Some(delay) = params.delay {
+            let Some(nbf) = now.checked_add(delay) else {
+                return HttpResponse::BadRequest().body("delay is too large.\n");
+            };
+            json_gift.insert("iat".to_string(), now.into());
+            json_gift.insert("nbf".to_string(), nbf.into());
+        }
+        if let Some(iss) = params.iss.as_ref().or(config.issuer.as_ref()) {
+            json_gift.insert("iss".to_string(), iss.clone().into());
+        }
+        if let Some(aud) = &config.audience {
+            json_gift.insert("aud".to_string(), aud.clone().into());
+        }
+        json_gift.insert("jti".to_string(), Uuid::new_v4().to_string().into());
+
+        match gift_cookie(&keys, &config, alg, &json_gift, params.encrypt) {
             Err(e) => {
                 println!("Error on encoding the gift: {}", e);
                 HttpResponse::InternalServerError().finish()
             }
-            Ok(gift) => HttpResponse::Ok().cookie(Cookie::new("gift", gift)).finish()
+            Ok(cookie) => HttpResponse::Ok().cookie(cookie).finish()
         }
     }
 
     #[get("/unwrap")]
-    async fn unpack(jwt_secret: web::Data<Vec<u8>>, req: HttpRequest) -> HttpResponse {
-        let gift_jwt = req.cookie("gift");
-        if gift_jwt.is_none() {
-            return HttpResponse::BadRequest().finish();
+    async fn unpack(
+        keys: web::Data<GiftKeys>,
+        config: web::Data<GiftConfig>,
+        revocations: web::Data<Revocations>,
+        req: HttpRequest
+    ) -> HttpResponse {
+        let mut jwt_token = match req.cookie("gift") {
+            None => return HttpResponse::BadRequest().finish(),
+            Some(cookie) => cookie.value().to_string()
+        };
+        // Encrypted gifts are compact JWEs with five segments wrapping the signed gift.
+        let encrypted = jwt_token.split('.').count() == 5;
+        if encrypted {
+            jwt_token = match keys.decrypt(&jwt_token) {
+                Err(e) => return HttpResponse::Unauthorized().body(format!("{}.\n", e)),
+                Ok(token) => token
+            };
         }
+        let alg = match decode_header(&jwt_token) {
+            Err(_) => return HttpResponse::BadRequest().finish(),
+            Ok(header) => header.alg
+        };
 
-        let jwt_token = gift_jwt.unwrap().to_string().split("=").skip(1).collect::<String>();
-        let decoding_key = DecodingKey::from_secret(jwt_secret.as_slice());
-
-        let mut validation = Validation::default();
-        validation.validate_exp = false;
-        validation.required_spec_claims = HashSet::from(["".to_string()]);
-
-        match decode::<Gift>(&jwt_token, &decoding_key, &validation) {
+        let gift = match keys.decode::<Gift>(&jwt_token, &gift_validation(alg, &config)) {
             Err(e) => {
+                if let Some(reason) = claim_rejection(e.kind()) {
+                    return HttpResponse::Unauthorized().body(reason);
+                }
                 println!("Message err: {:?}", e);
                 return HttpResponse::InternalServerError().finish();
             }
-            Ok(gift) => HttpResponse::Ok().json(gift.claims)
+            Ok(gift) => gift
+        };
+        match revoked(&revocations, &gift.claims).await {
+            Err(e) => revocation_failure(e),
+            Ok(true) => HttpResponse::Unauthorized().body("Gift revoked.\n"),
+            Ok(false) => {
+                let mut response = HttpResponse::Ok();
+                if let Some(refreshed) = refresh(&gift.claims, &config) {
+                    match gift_cookie(&keys, &config, alg, &refreshed, encrypted) {
+                        Err(e) => println!("Error on refreshing the gift: {}", e),
+                        Ok(cookie) => {
+                            response.cookie(cookie);
+                        }
+                    }
+                }
+                response.json(gift.claims)
+            }
         }
     }
 
     #[post("/decode")]
-    async fn unpack_olders(jwt_secret: web::Data<Vec<u8>>, bytes: web::Bytes) -> HttpResponse {
-        let token = String::from_utf8(bytes.to_vec()).unwrap();
-        let decoding_key = DecodingKey::from_rsa_pem(jwt_secret.as_slice())
-            .expect("Unable to generate decode key from PEM.");
+    async fn unpack_olders(keys: web::Data<GiftKeys>, revocations: web::Data<Revocations>, bytes: web::Bytes) -> HttpResponse {
+        let token = match String::from_utf8(bytes.to_vec()) {
+            Err(_) => return HttpResponse::BadRequest().finish(),
+            Ok(token) => token
+        };
 
         let mut validation = Validation::default();
-        validation.algorithms = vec![Algorithm::RS256, Algorithm::RS512];
+        validation.algorithms = vec![
+            Algorithm::RS256, Algorithm::RS512,
+            Algorithm::PS256, Algorithm::PS512,
+            Algorithm::ES256, Algorithm::ES384,
+            Algorithm::EdDSA
+        ];
         validation.validate_exp = false;
         validation.required_spec_claims = HashSet::from(["".to_string()]);
 
-        match decode::<Gift>(&token, &decoding_key, &validation) {
-            Ok(gift) => HttpResponse::Ok().json(gift.claims),
-            Err(e) => match e.kind() {
+        let gift = match keys.decode::<Gift>(&token, &validation) {
+            Err(e) => return match e.kind() {
                 ErrorKind::InvalidSignature => HttpResponse::Unauthorized().finish(),
                 _ => HttpResponse::BadRequest().finish()
+            },
+            Ok(gift) => gift
+        };
+        match revoked(&revocations, &gift.claims).await {
+            Err(e) => revocation_failure(e),
+            Ok(true) => HttpResponse::Unauthorized().finish(),
+            Ok(false) => HttpResponse::Ok()
+                .insert_header(("X-Gift-Algorithm", format!("{:?}", gift.header.alg)))
+                .json(gift.claims)
+        }
+    }
+
+    /// What `/16/inspect` found out about a token, nothing in it is trusted unless `valid`.
+    #[derive(Debug, Default, Serialize)]
+    struct Inspection {
+        encrypted: bool,
+        header: Option<Value>,
+        claims: Option<Value>,
+        algorithm: Option<Algorithm>,
+        signature_valid: bool,
+        valid: bool,
+        error: Option<String>
+    }
+    impl Inspection {
+        fn fail(mut self, reason: impl ToString) -> Self {
+            self.error = Some(reason.to_string());
+            self
+        }
+    }
+
+    fn failure_reason(kind: &ErrorKind) -> String {
+        match kind {
+            ErrorKind::InvalidToken => "Token is not made of three dot separated segments".to_string(),
+            ErrorKind::Base64(e) => format!("Malformed base64: {}", e),
+            ErrorKind::Json(e) => format!("Malformed JSON: {}", e),
+            ErrorKind::Utf8(e) => format!("Invalid UTF-8: {}", e),
+            ErrorKind::InvalidSignature => "Bad signature".to_string(),
+            ErrorKind::InvalidAlgorithm => "Algorithm not accepted for this key".to_string(),
+            ErrorKind::InvalidAlgorithmName => "Unknown algorithm".to_string(),
+            ErrorKind::ExpiredSignature => "Expired".to_string(),
+            ErrorKind::ImmatureSignature => "Not yet valid".to_string(),
+            ErrorKind::InvalidAudience => "Wrong audience".to_string(),
+            ErrorKind::InvalidIssuer => "Wrong issuer".to_string(),
+            ErrorKind::MissingRequiredClaim(claim) => format!("Missing required claim `{}`", claim),
+            other => format!("{:?}", other)
+        }
+    }
+
+    fn decode_segment(segment: &str) -> Result<Value, String> {
+        let bytes = URL_SAFE_NO_PAD.decode(segment).map_err(|e| format!("Malformed base64: {}", e))?;
+        serde_json::from_slice(&bytes).map_err(|e| format!("Malformed JSON: {}", e))
+    }
+
+    #[post("/inspect")]
+    async fn inspect(
+        keys: web::Data<GiftKeys>,
+        config: web::Data<GiftConfig>,
+        revocations: web::Data<Revocations>,
+        bytes: web::Bytes
+    ) -> HttpResponse {
+        let mut token = match String::from_utf8(bytes.to_vec()) {
+            Err(_) => return HttpResponse::BadRequest().finish(),
+            Ok(token) => token.trim().to_string()
+        };
+        let mut inspection = Inspection::default();
+
+        if token.split('.').count() == 5 {
+            inspection.encrypted = true;
+            token = match keys.decrypt(&token) {
+                Err(e) => return HttpResponse::Ok().json(inspection.fail(e)),
+                Ok(token) => token
+            };
+        }
+        let [header, claims, _] = token.split('.').collect::<Vec<&str>>()[..] else {
+            return HttpResponse::Ok().json(inspection.fail(failure_reason(&ErrorKind::InvalidToken)));
+        };
+
+        match decode_segment(header) {
+            Err(e) => return HttpResponse::Ok().json(inspection.fail(format!("Header: {}", e))),
+            Ok(header) => inspection.header = Some(header)
+        }
+        match decode_segment(claims) {
+            Err(e) => return HttpResponse::Ok().json(inspection.fail(format!("Claims: {}", e))),
+            Ok(claims) => inspection.claims = Some(claims)
+        }
+        let header = match decode_header(&token) {
+            Err(e) => return HttpResponse::Ok().json(inspection.fail(failure_reason(e.kind()))),
+            Ok(header) => header
+        };
+        inspection.algorithm = Some(header.alg);
+        if keys.decoding_keys(&header).is_empty() {
+            return HttpResponse::Ok().json(inspection.fail(match header.kid {
+                Some(kid) => format!("No key with kid `{}`", kid),
+                None => format!("No key for {:?}", header.alg)
+            }));
+        }
+
+        // The signature is checked on its own first so a failing claim doesn't hide it.
+        if let Err(e) = keys.decode::<Gift>(&token, &signature_validation(header.alg)) {
+            return HttpResponse::Ok().json(inspection.fail(failure_reason(e.kind())));
+        }
+        inspection.signature_valid = true;
+
+        let gift = match keys.decode::<Gift>(&token, &gift_validation(header.alg, &config)) {
+            Err(e) => return HttpResponse::Ok().json(inspection.fail(failure_reason(e.kind()))),
+            Ok(gift) => gift
+        };
+        match revoked(&revocations, &gift.claims).await {
+            Err(e) => revocation_failure(e),
+            Ok(true) => HttpResponse::Ok().json(inspection.fail("Revoked")),
+            Ok(false) => {
+                inspection.valid = true;
+                HttpResponse::Ok().json(inspection)
+            }
+        }
+    }
+
+    /// Revokes the gift sent in the body, or the one in the `gift` cookie when the body is empty.
+    /// Expired gifts can still be revoked, only the signature is checked.
+    #[post("/revoke")]
+    async fn revoke(
+        keys: web::Data<GiftKeys>,
+        revocations: web::Data<Revocations>,
+        req: HttpRequest,
+        bytes: web::Bytes
+    ) -> HttpResponse {
+        let mut token = match String::from_utf8(bytes.to_vec()) {
+            Err(_) => return HttpResponse::BadRequest().finish(),
+            Ok(body) if !body.trim().is_empty() => body.trim().to_string(),
+            Ok(_) => match req.cookie("gift") {
+                None => return HttpResponse::BadRequest().finish(),
+                Some(cookie) => cookie.value().to_string()
+            }
+        };
+        if token.split('.').count() == 5 {
+            token = match keys.decrypt(&token) {
+                Err(e) => return HttpResponse::Unauthorized().body(format!("{}.\n", e)),
+                Ok(token) => token
+            };
+        }
+        let alg = match decode_header(&token) {
+            Err(_) => return HttpResponse::BadRequest().finish(),
+            Ok(header) => header.alg
+        };
+
+        let gift = match keys.decode::<Gift>(&token, &signature_validation(alg)) {
+            Err(e) => return HttpResponse::Unauthorized().body(format!("{}.\n", failure_reason(e.kind()))),
+            Ok(gift) => gift.claims
+        };
+        let Some(jti) = gift.get("jti").and_then(Value::as_str) else {
+            return HttpResponse::BadRequest().body("Gift has no jti.\n");
+        };
+        match revocations.revoke(jti, gift.get("exp").and_then(Value::as_u64)).await {
+            Err(e) => {
+                println!("Error on revoking the gift: {}", e);
+                HttpResponse::InternalServerError().finish()
             }
+            Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "jti": jti }))
+        }
+    }
+
+    #[get("/.well-known/jwks.json")]
+    async fn jwks(keys: web::Data<GiftKeys>) -> HttpResponse {
+        HttpResponse::Ok().json(keys.jwks())
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct RotateParams {
+        kty: Option<KeyFamily>
+    }
+    #[post("/rotate")]
+    async fn rotate(keys: web::Data<GiftKeys>, params: web::Query<RotateParams>) -> HttpResponse {
+        // Generating an RSA key takes seconds, keep it off the worker serving the other requests.
+        let rotating = keys.clone();
+        let kty = params.kty;
+        match web::block(move || rotating.rotate(kty).map_err(|e| e.to_string())).await {
+            Err(e) => {
+                println!("Error on rotating the gift keys: {}", e);
+                HttpResponse::InternalServerError().finish()
+            },
+            Ok(Err(e)) => HttpResponse::BadRequest().body(format!("{}.\n", e)),
+            Ok(Ok(key)) => HttpResponse::Ok().json(keys.jwks().find(key.kid()))
+        }
+    }
+
+    #[delete("/keys/{kid}")]
+    async fn retire(keys: web::Data<GiftKeys>, kid: web::Path<String>) -> HttpResponse {
+        match keys.retire(&kid) {
+            Err(e) => HttpResponse::Conflict().body(format!("{}.\n", e)),
+            Ok(false) => HttpResponse::NotFound().finish(),
+            Ok(true) => HttpResponse::NoContent().finish()
         }
     }
 }
@@ -73,4 +999,62 @@ pub fn scope() -> Scope {
         .service(gift::pack)
         .service(gift::unpack)
         .service(gift::unpack_olders)
+        .service(gift::inspect)
+        .service(gift::revoke)
+        .service(gift::jwks)
+        .service(gift::rotate)
+        .service(gift::retire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> GiftKeys {
+        GiftKeys::from_pems(b"secret", None, "").unwrap()
+            .with_encryption_key(&URL_SAFE_NO_PAD.encode([7u8; 32])).unwrap()
+    }
+
+    /// Flips a bit of the decoded segment `index` of a compact JWE.
+    fn tamper(token: &str, index: usize) -> String {
+        let mut segments = token.split('.').map(str::to_string).collect::<Vec<_>>();
+        let mut bytes = URL_SAFE_NO_PAD.decode(&segments[index]).unwrap();
+        bytes[0] ^= 1;
+        segments[index] = URL_SAFE_NO_PAD.encode(bytes);
+        segments.join(".")
+    }
+
+    #[test]
+    fn encrypted_gift_round_trips() {
+        let keys = keys();
+        let sealed = keys.encrypt("header.claims.signature").unwrap();
+        assert_eq!(sealed.split('.').count(), 5);
+        assert!(!sealed.contains("claims"));
+        assert_eq!(keys.decrypt(&sealed).unwrap(), "header.claims.signature");
+    }
+
+    #[test]
+    fn tampered_encrypted_gift_is_rejected() {
+        let keys = keys();
+        let sealed = keys.encrypt("header.claims.signature").unwrap();
+        // The protected header is authenticated data, the IV, ciphertext and tag are sealed.
+        for index in [0, 2, 3, 4] {
+            assert!(keys.decrypt(&tamper(&sealed, index)).is_err(), "segment {} tampered", index);
+        }
+    }
+
+    #[test]
+    fn encrypted_gift_needs_the_same_key() {
+        let sealed = keys().encrypt("header.claims.signature").unwrap();
+        let other = GiftKeys::from_pems(b"secret", None, "").unwrap()
+            .with_encryption_key(&URL_SAFE_NO_PAD.encode([8u8; 32])).unwrap();
+        assert!(other.decrypt(&sealed).is_err());
+        assert!(GiftKeys::from_pems(b"secret", None, "").unwrap().decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn encryption_key_must_be_256_bits() {
+        let keys = GiftKeys::from_pems(b"secret", None, "").unwrap();
+        assert!(keys.with_encryption_key(&URL_SAFE_NO_PAD.encode([7u8; 16])).is_err());
+    }
 }
@@ -1,12 +1,113 @@
-use actix_web::{web, Scope};
-use rand::{self, distributions::Alphanumeric, Rng};
-use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, types::{chrono::{DateTime, Utc}, Uuid}};
+use std::{future::{ready, Ready}, sync::Arc, time::Duration};
 
-#[derive(Deserialize)]
+use actix_web::{
+    dev::Payload,
+    error::{ErrorBadRequest, InternalError},
+    http::StatusCode,
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse, Scope
+};
+use async_graphql::InputObject;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{postgres::{types::PgInterval, PgListener, PgPool}, prelude::FromRow, types::{chrono::{DateTime, Utc}, Uuid}};
+use tokio::sync::broadcast;
+use crate::{auth::Principal, problem::Problem};
+
+#[derive(Deserialize, InputObject)]
+#[graphql(name = "QuoteInput")]
 pub struct NewQuote {
     author: String,
-    quote: String
+    quote: String,
+    /// Left out, an edit keeps the tags the quote already has.
+    tags: Option<Vec<String>>
+}
+impl NewQuote {
+    /// Trims the quote and normalizes its tags, then lists what's wrong with it.
+    fn validate(&mut self) -> Vec<FieldError> {
+        self.author = self.author.trim().to_string();
+        self.quote = self.quote.trim().to_string();
+        let mut errors = validate_quote(&self.author, &self.quote);
+        if let Some(tags) = &mut self.tags {
+            errors.extend(validate_tags(tags));
+        }
+        errors
+    }
+}
+
+pub const MAX_AUTHOR_CHARS: usize = 200;
+pub const MAX_QUOTE_CHARS: usize = 1000;
+pub const MAX_TAGS: usize = 10;
+pub const MAX_TAG_CHARS: usize = 50;
+/// Joins the tags of a quote in the single column a CSV export has for them.
+pub const TAG_SEPARATOR: char = ';';
+
+/// Tags are matched case insensitively, so they're stored trimmed and lowercased.
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+#[derive(Serialize, Debug)]
+struct FieldError {
+    field: &'static str,
+    message: String
+}
+
+/// Checks an already trimmed author and quote against the rules every stored quote follows.
+fn validate_quote(author: &str, quote: &str) -> Vec<FieldError> {
+    let mut errors = vec![];
+    for (field, value, max_chars) in [("author", author, MAX_AUTHOR_CHARS), ("quote", quote, MAX_QUOTE_CHARS)] {
+        if value.is_empty() {
+            errors.push(FieldError { field, message: format!("{} must not be empty", field) });
+        } else if value.chars().count() > max_chars {
+            errors.push(FieldError { field, message: format!("{} must be at most {} characters", field, max_chars) });
+        }
+    }
+    errors
+}
+
+/// Normalizes, sorts and dedups the tags of a quote, then lists what's wrong with them.
+fn validate_tags(tags: &mut Vec<String>) -> Vec<FieldError> {
+    *tags = tags.iter().map(|tag| normalize_tag(tag)).collect();
+    tags.sort();
+    tags.dedup();
+    let mut errors = vec![];
+    if tags.len() > MAX_TAGS {
+        errors.push(FieldError { field: "tags", message: format!("tags must be at most {}", MAX_TAGS) });
+    }
+    if tags.iter().any(|tag| tag.is_empty() || tag.chars().count() > MAX_TAG_CHARS) {
+        errors.push(FieldError {
+            field: "tags",
+            message: format!("tags must not be empty nor longer than {} characters", MAX_TAG_CHARS)
+        });
+    }
+    if tags.iter().any(|tag| tag.contains(TAG_SEPARATOR)) {
+        errors.push(FieldError { field: "tags", message: format!("tags must not contain {:?}", TAG_SEPARATOR) });
+    }
+    errors
+}
+
+/// 400 listing the quote rules the request broke.
+fn invalid(errors: Vec<FieldError>) -> Problem {
+    Problem::new(StatusCode::BAD_REQUEST, "Invalid quote", "The quote breaks the quote rules.").with("errors", errors)
+}
+
+/// Whether the query failed because the same author and quote are already live.
+fn is_duplicate(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|db| db.is_unique_violation() && db.constraint() == Some("quotes_author_quote_key"))
+}
+
+/// 409 for a duplicate quote, 500 for anything else.
+fn write_failure(e: sqlx::Error) -> HttpResponse {
+    match is_duplicate(&e) {
+        true => Problem::new(StatusCode::CONFLICT, "Duplicate quote", "This author already has this quote.").response(),
+        false => {
+            println!("Failed on writing the quote: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
 }
 
 #[derive(Serialize, Debug, Clone, FromRow)]
@@ -15,53 +116,190 @@ pub struct Quote {
     author: String,
     quote: String,
     created_at: DateTime<Utc>,
-    version: i32
+    version: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>
+}
+
+#[derive(Serialize, Debug, FromRow)]
+pub struct TagCount {
+    tag: String,
+    count: i64
+}
+
+/// One version of a quote, as recorded in `quote_revisions` on every insert and edit.
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct QuoteRevision {
+    quote_id: Uuid,
+    version: i32,
+    author: String,
+    quote: String,
+    revised_at: DateTime<Utc>
 }
 
-type PaginatorPages = Vec<(String, Vec<Quote>)>;
+/// Tenant of the requests that don't name one, so a single team deployment works as before.
+pub const DEFAULT_TENANT: &str = "default";
+pub const TENANT_HEADER: &str = "X-Tenant-Id";
+
+/// Whether a bare `X-Tenant-Id` header is trusted, API keys get their tenant from `AUTH_API_KEYS`.
+pub struct Tenants {
+    trust_header: bool
+}
+impl Tenants {
+    pub fn new(trust_header: bool) -> Self {
+        Tenants { trust_header }
+    }
+}
+
+/// Tenant ids are short and only made of ASCII letters, digits, `-` and `_`.
+pub fn valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty() && tenant.len() <= 64 && tenant.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The tenant whose quotes a request reads and writes, every `crud` query is scoped to it.
 #[derive(Debug, Clone)]
+pub struct Tenant(String);
+impl Tenant {
+    pub fn id(&self) -> &str {
+        &self.0
+    }
+
+    fn of(req: &HttpRequest) -> Result<Self, actix_web::Error> {
+        // Authenticated callers work on the tenant they were granted.
+        if let Some(principal) = req.extensions().get::<Principal>() {
+            return match principal.tenant.as_deref() {
+                None => Ok(Tenant(DEFAULT_TENANT.to_string())),
+                Some(tenant) if valid_tenant(tenant) => Ok(Tenant(tenant.to_string())),
+                Some(_) => Err(ErrorBadRequest("Invalid tenant"))
+            };
+        }
+        let trust_header = req.app_data::<web::Data<Tenants>>().is_some_and(|tenants| tenants.trust_header);
+        match req.headers().get(TENANT_HEADER).map(|value| value.to_str().unwrap_or_default()) {
+            Some(tenant) if trust_header => match valid_tenant(tenant) {
+                false => Err(ErrorBadRequest("Invalid tenant")),
+                true => Ok(Tenant(tenant.to_string()))
+            },
+            _ => Ok(Tenant(DEFAULT_TENANT.to_string()))
+        }
+    }
+}
+impl FromRequest for Tenant {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Tenant::of(req))
+    }
+}
+
+/// Page size of `/19/list` and `/19/search` when none is configured.
+pub const PAGE_SIZE: usize = 3;
+
+/// Position after the last quote of a page, `/19/list` keeps going from there.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Cursor {
+    created_at: DateTime<Utc>,
+    id: Uuid,
+    page: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct SearchFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    until: Option<DateTime<Utc>>
+}
+
+/// Position after the last match of a `/19/search` page. Ranks don't move as quotes are added or
+/// removed, so it stays right under concurrent writes, the filter sticks to the next pages.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct SearchCursor {
+    rank: f32,
+    created_at: DateTime<Utc>,
+    id: Uuid,
+    page: usize,
+    filter: SearchFilter
+}
+
+/// A search match along with its rank, to build the cursor of the next page.
+#[derive(Debug, FromRow)]
+struct Ranked {
+    #[sqlx(flatten)]
+    quote: Quote,
+    rank: f32
+}
+
+/// Issues the `/19/list` and `/19/search` tokens: a keyset cursor signed with HMAC-SHA256, so pages
+/// are read straight from the database and any instance sharing the secret can continue a listing.
 pub struct Paginator {
-    pages: PaginatorPages,
-    traversed: bool
+    secret: Vec<u8>,
+    page_size: usize
 }
 impl Paginator {
-    pub fn new() -> Self {
-        Paginator {
-            pages: vec![],
-            traversed: false
-        }
-    }
-    pub fn set_pages(&mut self, quotes: Vec<Quote>) {
-        quotes.chunks(3).for_each(|chunk| {
-            let token: String = rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(16)
-                .map(char::from)
-                .collect();
-            self.pages.push((token, chunk.to_vec()));
-        });
+    pub fn new(secret: &[u8], page_size: usize) -> Self {
+        Paginator { secret: secret.to_vec(), page_size: page_size.max(1) }
     }
-    pub fn next_page(&mut self, token: Option<String>) -> Option<PageList> {
-        let quotes_pos: Option<usize> = match &token {
-            None => Some(0),
-            Some(token) => self.pages.iter().position(|(t, _)| { *t == *token })
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size")
+    }
+
+    fn token<T: Serialize>(&self, cursor: &T) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor).expect("Cursor is serializable"));
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    /// `None` for tokens that are malformed or weren't signed with our secret.
+    fn cursor<T: DeserializeOwned>(&self, token: &str) -> Option<T> {
+        let (payload, signature) = token.split_once('.')?;
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?).ok()?;
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+
+    /// Builds the page from up to `page_size + 1` quotes, the extra one only tells there's a next page.
+    fn page(&self, mut quotes: Vec<Quote>, page: usize, tag: Option<String>) -> PageList {
+        let next_token = match quotes.len() > self.page_size {
+            false => None,
+            true => {
+                quotes.truncate(self.page_size);
+                quotes.last().map(|last| self.token(&Cursor { created_at: last.created_at, id: last.id, page: page + 1, tag }))
+            }
         };
-        if quotes_pos.is_none() {
-            return None;
-        }
+        PageList { quotes, page, next_token }
+    }
 
-        let next_pos = quotes_pos.unwrap().wrapping_add(1);
-        Some(PageList {
-            quotes: self.pages[quotes_pos.unwrap()].1.clone(),
-            page: next_pos,
-            next_token: match self.pages.get(next_pos) {
-                None => {
-                    self.traversed = true;
-                    None
-                },
-                Some((token, _)) => Some(token.clone())
+    /// Same as `page` for up to `page_size + 1` search matches.
+    fn search_page(&self, mut matches: Vec<Ranked>, page: usize, filter: SearchFilter) -> PageList {
+        let next_token = match matches.len() > self.page_size {
+            false => None,
+            true => {
+                matches.truncate(self.page_size);
+                matches.last().map(|last| self.token(&SearchCursor {
+                    rank: last.rank,
+                    created_at: last.quote.created_at,
+                    id: last.quote.id,
+                    page: page + 1,
+                    filter
+                }))
             }
-        })
+        };
+        PageList { quotes: matches.into_iter().map(|m| m.quote).collect(), page, next_token }
     }
 }
 #[derive(Serialize, Debug)]
@@ -71,139 +309,1257 @@ pub struct PageList {
     next_token: Option<String>,
 }
 
+/// How long removed quotes stay in `/19/trash` when no retention is configured.
+pub const TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Deletes for good the quotes removed more than `retention` ago, along with their revisions.
+pub async fn purge(pgpool: &PgPool, retention: Duration) -> Result<u64, sqlx::Error> {
+    sqlx::query("DELETE FROM quotes WHERE deleted_at < CURRENT_TIMESTAMP - $1::INTERVAL")
+        .bind(PgInterval::try_from(retention).map_err(sqlx::Error::Encode)?)
+        .execute(pgpool).await
+        .map(|result| result.rows_affected())
+}
+
+/// Runs [`purge`] every `every` for as long as the server lives.
+pub fn spawn_purge(pgpool: PgPool, retention: Duration, every: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            match purge(&pgpool, retention).await {
+                Err(e) => println!("Failed on purging the trash: {:?}", e),
+                Ok(0) => {},
+                Ok(purged) => println!("Purged {} quotes from the trash", purged)
+            }
+        }
+    });
+}
+
+/// Channel the `quotes` trigger notifies on.
+const CHANGES_CHANNEL: &str = "quote_changes";
+/// Changes kept for the `/19/changes` streams that fall behind, the slower ones are told they lagged.
+const CHANGES_BUFFER: usize = 256;
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ChangeKind {
+    Create,
+    Update,
+    Delete
+}
+impl ChangeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Create => "create",
+            ChangeKind::Update => "update",
+            ChangeKind::Delete => "delete"
+        }
+    }
+}
+
+/// Payload of the trigger's notifications.
+#[derive(Deserialize, Debug)]
+struct ChangeNotification {
+    op: ChangeKind,
+    id: Uuid,
+    tenant: String
+}
+
+#[derive(Debug, Clone)]
+struct QuoteChange {
+    kind: ChangeKind,
+    tenant: String,
+    quote: Quote
+}
+
+/// Relays the quote changes the database notifies to every `/19/changes` stream, on a single
+/// connection per instance however many streams are open.
+pub struct ChangeFeed {
+    sender: broadcast::Sender<Arc<QuoteChange>>
+}
+impl ChangeFeed {
+    /// Listens for as long as the server lives, starting over a few seconds after a failure.
+    pub fn spawn(pgpool: PgPool) -> Self {
+        let (sender, _) = broadcast::channel(CHANGES_BUFFER);
+        let feed = ChangeFeed { sender: sender.clone() };
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = relay(&pgpool, &sender).await {
+                    println!("Failed on listening for quote changes: {:?}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+        feed
+    }
+}
+
+async fn relay(pgpool: &PgPool, sender: &broadcast::Sender<Arc<QuoteChange>>) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pgpool).await?;
+    listener.listen(CHANGES_CHANNEL).await?;
+    loop {
+        let notification = listener.recv().await?;
+        if sender.receiver_count() == 0 {
+            continue;
+        }
+        let change = match serde_json::from_str::<ChangeNotification>(notification.payload()) {
+            Err(e) => {
+                println!("Failed on reading a quote change: {:?}", e);
+                continue;
+            },
+            Ok(change) => change
+        };
+        // Read once the change committed, so it comes with the tags written along with it.
+        let quote = sqlx::query_as::<_, Quote>("SELECT id, author, quote, created_at, version, deleted_at, quote_tag_names(id) AS tags
+            FROM quotes WHERE id = $1")
+            .bind(change.id)
+            .fetch_optional(pgpool).await?;
+        // Nothing to tell about a quote purged since.
+        if let Some(quote) = quote {
+            let _ = sender.send(Arc::new(QuoteChange { kind: change.op, tenant: change.tenant, quote }));
+        }
+    }
+}
+
+/// The quote queries behind both `crud` and `graphql`, every one scoped to a tenant.
+mod store {
+    use crate::challenges::day_19::{Cursor, NewQuote, PageList, Paginator, Quote, Ranked, SearchCursor, SearchFilter, Tenant};
+
+    use sqlx::{postgres::PgPool, types::Uuid};
+
+    pub async fn cite(pgpool: &PgPool, tenant: &Tenant, id: Uuid) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as::<_, Quote>("SELECT *, quote_tag_names(id) AS tags FROM quotes WHERE id = $1 AND tenant = $2 AND deleted_at IS NULL")
+            .bind(id)
+            .bind(tenant.id())
+            .fetch_optional(pgpool).await
+    }
+
+    /// Version of a live quote, to tell a stale version from a missing quote once a compare-and-set matched nothing.
+    pub async fn version(pgpool: &PgPool, tenant: &Tenant, id: Uuid) -> Result<Option<i32>, sqlx::Error> {
+        sqlx::query_scalar::<_, i32>("SELECT version FROM quotes WHERE id = $1 AND deleted_at IS NULL AND tenant = $2")
+            .bind(id)
+            .bind(tenant.id())
+            .fetch_optional(pgpool).await
+    }
+
+    /// Inserts an already validated quote along with its tags.
+    pub async fn draft(pgpool: &PgPool, tenant: &Tenant, quote: &NewQuote) -> Result<Quote, sqlx::Error> {
+        sqlx::query_as::<_, Quote>("WITH inserted AS (
+                INSERT INTO quotes (id, author, quote, tenant) VALUES ($1, $2, $3, $5)
+                RETURNING id, author, quote, created_at, version, deleted_at
+            ), named AS (
+                INSERT INTO tags (name) SELECT unnest($4::TEXT[]) FROM inserted
+                ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+                RETURNING id
+            ), linked AS (
+                INSERT INTO quote_tags (quote_id, tag_id) SELECT inserted.id, named.id FROM inserted, named
+            )
+            SELECT inserted.*, COALESCE($4::TEXT[], '{}') AS tags FROM inserted")
+            .bind(Uuid::new_v4())
+            .bind(&quote.author)
+            .bind(&quote.quote)
+            .bind(&quote.tags)
+            .bind(tenant.id())
+            .fetch_one(pgpool).await
+    }
+
+    /// Sends a quote to the trash if its version is one of `expected`, any version will do when `None`.
+    pub async fn remove(pgpool: &PgPool, tenant: &Tenant, id: Uuid, expected: Option<&[i32]>) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as::<_, Quote>("UPDATE quotes SET deleted_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND tenant = $3 AND deleted_at IS NULL AND ($2::INT[] IS NULL OR version = ANY($2))
+            RETURNING id, author, quote, created_at, version, deleted_at, quote_tag_names(id) AS tags")
+            .bind(id)
+            .bind(expected)
+            .bind(tenant.id())
+            .fetch_optional(pgpool).await
+    }
+
+    /// Bumps the version in the same statement that checks it, so concurrent edits can't both apply.
+    pub async fn undo(
+        pgpool: &PgPool,
+        tenant: &Tenant,
+        id: Uuid,
+        expected: Option<&[i32]>,
+        quote: &NewQuote
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as::<_, Quote>("WITH updated AS (
+                UPDATE quotes SET author = $1, quote = $2, version = version + 1
+                WHERE id = $3 AND tenant = $6 AND deleted_at IS NULL AND ($4::INT[] IS NULL OR version = ANY($4))
+                RETURNING id, author, quote, created_at, version, deleted_at
+            ), named AS (
+                INSERT INTO tags (name) SELECT unnest($5::TEXT[]) FROM updated
+                ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+                RETURNING id
+            ), unlinked AS (
+                DELETE FROM quote_tags
+                WHERE $5::TEXT[] IS NOT NULL AND quote_id IN (SELECT id FROM updated) AND tag_id NOT IN (SELECT id FROM named)
+            ), linked AS (
+                INSERT INTO quote_tags (quote_id, tag_id) SELECT updated.id, named.id FROM updated, named
+                ON CONFLICT DO NOTHING
+            )
+            SELECT updated.*, COALESCE($5::TEXT[], quote_tag_names(updated.id)) AS tags FROM updated")
+            .bind(&quote.author)
+            .bind(&quote.quote)
+            .bind(id)
+            .bind(expected)
+            .bind(&quote.tags)
+            .bind(tenant.id())
+            .fetch_optional(pgpool).await
+    }
+
+    /// Keyset page of the live quotes after `cursor`, oldest first.
+    pub async fn list(
+        pgpool: &PgPool,
+        tenant: &Tenant,
+        paginator: &Paginator,
+        cursor: Option<Cursor>,
+        tag: Option<String>
+    ) -> Result<PageList, sqlx::Error> {
+        let quotes = sqlx::query_as::<_, Quote>("SELECT id, author, quote, created_at, version, deleted_at, quote_tag_names(id) AS tags FROM quotes
+            WHERE deleted_at IS NULL AND tenant = $5 AND ($1::TIMESTAMPTZ IS NULL OR (created_at, id) > ($1, $2))
+                AND ($4::TEXT IS NULL OR $4 = ANY(quote_tag_names(id)))
+            ORDER BY created_at ASC, id ASC
+            LIMIT $3")
+            .bind(cursor.as_ref().map(|c| c.created_at))
+            .bind(cursor.as_ref().map(|c| c.id))
+            .bind(paginator.page_size as i64 + 1)
+            .bind(&tag)
+            .bind(tenant.id())
+            .fetch_all(pgpool).await?;
+        Ok(paginator.page(quotes, cursor.map_or(1, |c| c.page), tag))
+    }
+
+    /// Ranks the matches of `q` against the author and the quote, then pages through them by rank.
+    pub async fn search(
+        pgpool: &PgPool,
+        tenant: &Tenant,
+        paginator: &Paginator,
+        cursor: Option<SearchCursor>,
+        filter: SearchFilter
+    ) -> Result<PageList, sqlx::Error> {
+        let matches = sqlx::query_as::<_, Ranked>("WITH matches AS (
+                SELECT id, author, quote, created_at, version, deleted_at,
+                    ts_rank(search, websearch_to_tsquery('english', COALESCE($1, ''))) AS rank
+                FROM quotes
+                WHERE deleted_at IS NULL AND tenant = $5
+                    AND ($1::TEXT IS NULL OR search @@ websearch_to_tsquery('english', $1))
+                    AND ($2::TEXT IS NULL OR lower(author) = lower($2))
+                    AND ($3::TIMESTAMPTZ IS NULL OR created_at >= $3)
+                    AND ($4::TIMESTAMPTZ IS NULL OR created_at < $4)
+            )
+            SELECT *, quote_tag_names(id) AS tags FROM matches
+            WHERE $6::REAL IS NULL OR rank < $6 OR (rank = $6 AND (created_at, id) > ($7, $8))
+            ORDER BY rank DESC, created_at ASC, id ASC
+            LIMIT $9")
+            .bind(&filter.q)
+            .bind(&filter.author)
+            .bind(filter.since)
+            .bind(filter.until)
+            .bind(tenant.id())
+            .bind(cursor.as_ref().map(|c| c.rank))
+            .bind(cursor.as_ref().map(|c| c.created_at))
+            .bind(cursor.as_ref().map(|c| c.id))
+            .bind(paginator.page_size as i64 + 1)
+            .fetch_all(pgpool).await?;
+        Ok(paginator.search_page(matches, cursor.map_or(1, |c| c.page), filter))
+    }
+}
+
 mod crud {
-    use std::{mem, str::FromStr, sync::Mutex};
-    use crate::challenges::day_19::{NewQuote, Quote, Paginator};
+    use std::str::FromStr;
+    use crate::challenges::day_19::{
+        invalid, normalize_tag, store, write_failure, Cursor, NewQuote, Quote, QuoteRevision, Paginator, SearchCursor, SearchFilter, TagCount, Tenant
+    };
 
-    use actix_web::{delete, get, post, put, web, HttpResponse};
+    use actix_web::{
+        delete, get, http::header::{self, EntityTag, ETag, Header, IfMatch}, post, put, web, HttpRequest, HttpResponse
+    };
     use serde::Deserialize;
-    use sqlx::{postgres::{PgPool, PgQueryResult}, types::Uuid};
+    use sqlx::{postgres::{PgPool, PgQueryResult}, types::{chrono::{NaiveDate, Utc}, Uuid}};
+
+    fn etag(quote: &Quote) -> ETag {
+        version_etag(quote.version)
+    }
+
+    fn version_etag(version: i32) -> ETag {
+        ETag(EntityTag::new_strong(version.to_string()))
+    }
+
+    /// The versions an `If-Match` header allows, `None` when it's missing or any version will do. Weak
+    /// tags never match, as `If-Match` uses the strong comparison, so a header without a strong tag
+    /// allows no version at all and the write fails with 412.
+    fn expected_versions(req: &HttpRequest) -> Option<Vec<i32>> {
+        // actix parses a missing header as an empty list, look for it first.
+        if !req.headers().contains_key(header::IF_MATCH) {
+            return None;
+        }
+        match IfMatch::parse(req) {
+            Ok(IfMatch::Any) => None,
+            Ok(IfMatch::Items(tags)) => Some(tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.tag().parse().ok())
+                .collect()),
+            Err(_) => Some(vec![])
+        }
+    }
+
+    /// Tells a stale `If-Match` (412) from a missing quote (404) once the compare-and-set matched nothing.
+    async fn conflict(pgpool: &PgPool, tenant: &Tenant, id: Uuid, expected: Option<Vec<i32>>) -> HttpResponse {
+        if expected.is_none() {
+            return HttpResponse::NotFound().finish();
+        }
+        match store::version(pgpool, tenant, id).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Ok(Some(version)) => HttpResponse::PreconditionFailed().insert_header(version_etag(version)).finish()
+        }
+    }
 
+    /// Sends every live quote of the tenant to the trash, other tenants keep theirs.
     #[post("/reset")]
-    async fn reset(pgpool: web::Data<PgPool>) -> HttpResponse {
-        let _: PgQueryResult = sqlx::query("DELETE FROM quotes").execute(pgpool.get_ref()).await.unwrap();
+    async fn reset(pgpool: web::Data<PgPool>, tenant: Tenant) -> HttpResponse {
+        let _: PgQueryResult = sqlx::query("UPDATE quotes SET deleted_at = CURRENT_TIMESTAMP WHERE deleted_at IS NULL AND tenant = $1")
+            .bind(tenant.id())
+            .execute(pgpool.get_ref()).await.unwrap();
         HttpResponse::Ok().finish()
     }
 
     #[post("/draft")]
-    async fn draft(pgpool: web::Data<PgPool>, json: web::Json<NewQuote>) -> HttpResponse {
-        match sqlx::query_as::<_, Quote>("INSERT INTO quotes (id, author, quote) VALUES ($1, $2, $3)
-            RETURNING id, author, quote, created_at, version")
-            .bind(Uuid::new_v4())
-            .bind(&json.author)
-            .bind(&json.quote)
-            .fetch_one(pgpool.get_ref()).await {
-            Err(_) => HttpResponse::Ok().finish(),
-            Ok(quote) => HttpResponse::Created().json(quote)
+    async fn draft(pgpool: web::Data<PgPool>, tenant: Tenant, json: web::Json<NewQuote>) -> HttpResponse {
+        let mut json = json.into_inner();
+        let errors = json.validate();
+        if !errors.is_empty() {
+            return invalid(errors).response();
+        }
+        match store::draft(pgpool.get_ref(), &tenant, &json).await {
+            Err(e) => write_failure(e),
+            Ok(quote) => HttpResponse::Created().insert_header(etag(&quote)).json(quote)
         }
     }
 
     #[delete("/remove/{id}")]
-    async fn remove(pgpool: web::Data<PgPool>, id: web::Path<String>) -> HttpResponse {
+    async fn remove(pgpool: web::Data<PgPool>, tenant: Tenant, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
         let uuid = Uuid::from_str(&id);
         if uuid.is_err() {
             return HttpResponse::BadRequest().finish();
         }
-        match sqlx::query_as::<_, Quote>(
-            "DELETE FROM quotes WHERE id = $1 RETURNING id, author, quote, created_at, version"
-            ).bind(uuid.unwrap())
-            .fetch_one(pgpool.get_ref()).await {
-            Err(_) => HttpResponse::NotFound().finish(),
-            Ok(quote) => HttpResponse::Ok().json(quote)
+        let quote_id = uuid.unwrap();
+        let expected = expected_versions(&req);
+        match store::remove(pgpool.get_ref(), &tenant, quote_id, expected.as_deref()).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(None) => conflict(pgpool.get_ref(), &tenant, quote_id, expected).await,
+            Ok(Some(quote)) => HttpResponse::Ok().json(quote)
         }
     }
 
     #[get("/cite/{id}")]
-    async fn cite(pgpool: web::Data<PgPool>, id: web::Path<String>) -> HttpResponse {
+    async fn cite(pgpool: web::Data<PgPool>, tenant: Tenant, id: web::Path<String>) -> HttpResponse {
         let uuid = Uuid::from_str(&id);
         if uuid.is_err() {
             return HttpResponse::BadRequest().finish();
         }
-        match sqlx::query_as::<_, Quote>("SELECT * FROM quotes WHERE id = $1")
-            .bind(uuid.unwrap())
-            .fetch_one(pgpool.get_ref()).await {
-            Err(_) => HttpResponse::NotFound().finish(),
-            Ok(quote) => HttpResponse::Ok().json(quote)
+        match store::cite(pgpool.get_ref(), &tenant, uuid.unwrap()).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Ok(Some(quote)) => HttpResponse::Ok().insert_header(etag(&quote)).json(quote)
         }
     }
 
     #[put("/undo/{id}")]
-    async fn undo(pgpool: web::Data<PgPool>, id: web::Path<String>, json: web::Json<NewQuote>) -> HttpResponse {
+    async fn undo(
+        pgpool: web::Data<PgPool>,
+        tenant: Tenant,
+        id: web::Path<String>,
+        req: HttpRequest,
+        json: web::Json<NewQuote>
+    ) -> HttpResponse {
         let uuid = Uuid::from_str(&id);
         if uuid.is_err() {
             return HttpResponse::BadRequest().finish();
         }
         let quote_id = uuid.unwrap();
-        let quote = sqlx::query_as::<_, Quote>("SELECT * FROM quotes WHERE id = $1")
-            .bind(&quote_id)
-            .fetch_one(pgpool.get_ref()).await;
-        if quote.is_err() {
-            return HttpResponse::NotFound().finish();
+        let mut json = json.into_inner();
+        let errors = json.validate();
+        if !errors.is_empty() {
+            return invalid(errors).response();
+        }
+        let expected = expected_versions(&req);
+        match store::undo(pgpool.get_ref(), &tenant, quote_id, expected.as_deref(), &json).await {
+            Err(e) => write_failure(e),
+            Ok(None) => conflict(pgpool.get_ref(), &tenant, quote_id, expected).await,
+            Ok(Some(quote)) => HttpResponse::Ok().insert_header(etag(&quote)).json(quote)
+        }
+    }
+
+    /// Every tag in use with the number of live quotes carrying it, most used first.
+    #[get("/tags")]
+    async fn tag_counts(pgpool: web::Data<PgPool>, tenant: Tenant) -> HttpResponse {
+        match sqlx::query_as::<_, TagCount>("SELECT tags.name AS tag, COUNT(*) AS count
+            FROM tags
+                JOIN quote_tags ON quote_tags.tag_id = tags.id
+                JOIN quotes ON quotes.id = quote_tags.quote_id AND quotes.deleted_at IS NULL AND quotes.tenant = $1
+            GROUP BY tags.name
+            ORDER BY count DESC, tag ASC")
+            .bind(tenant.id())
+            .fetch_all(pgpool.get_ref()).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(counts) => HttpResponse::Ok().json(counts)
+        }
+    }
+
+    #[get("/history/{id}")]
+    async fn history(pgpool: web::Data<PgPool>, tenant: Tenant, id: web::Path<String>) -> HttpResponse {
+        let uuid = Uuid::from_str(&id);
+        if uuid.is_err() {
+            return HttpResponse::BadRequest().finish();
         }
-        let version = quote.unwrap().version.wrapping_add(1);
+        match sqlx::query_as::<_, QuoteRevision>("SELECT quote_id, version, author, quote, revised_at
+            FROM quote_revisions
+            WHERE quote_id = $1 AND EXISTS (SELECT 1 FROM quotes WHERE id = $1 AND tenant = $2)
+            ORDER BY version ASC")
+            .bind(uuid.unwrap())
+            .bind(tenant.id())
+            .fetch_all(pgpool.get_ref()).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(revisions) if revisions.is_empty() => HttpResponse::NotFound().finish(),
+            Ok(revisions) => HttpResponse::Ok().json(revisions)
+        }
+    }
 
-        match sqlx::query_as::<_, Quote>("UPDATE quotes SET author = $1, quote = $2, version = $3 WHERE id = $4
-            RETURNING id, author, quote, created_at, version")
-            .bind(&json.author)
-            .bind(&json.quote)
+    /// Restores the content of an earlier revision as the next version, so the revert itself stays in the history.
+    #[put("/revert/{id}/{version}")]
+    async fn revert(pgpool: web::Data<PgPool>, tenant: Tenant, path: web::Path<(String, i32)>) -> HttpResponse {
+        let (id, version) = path.into_inner();
+        let uuid = Uuid::from_str(&id);
+        if uuid.is_err() {
+            return HttpResponse::BadRequest().finish();
+        }
+        match sqlx::query_as::<_, Quote>("UPDATE quotes SET author = r.author, quote = r.quote, version = quotes.version + 1
+            FROM quote_revisions r
+            WHERE quotes.id = $1 AND quotes.tenant = $3 AND quotes.deleted_at IS NULL AND r.quote_id = quotes.id AND r.version = $2
+            RETURNING quotes.id, quotes.author, quotes.quote, quotes.created_at, quotes.version, quotes.deleted_at, quote_tag_names(quotes.id) AS tags")
+            .bind(uuid.unwrap())
             .bind(version)
-            .bind(&quote_id)
-            .fetch_one(pgpool.get_ref()).await {
-            Err(_) => HttpResponse::NotFound().finish(),
-            Ok(quote) => HttpResponse::Ok().json(quote)
+            .bind(tenant.id())
+            .fetch_optional(pgpool.get_ref()).await {
+            Err(e) => write_failure(e),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Ok(Some(quote)) => HttpResponse::Ok().insert_header(etag(&quote)).json(quote)
+        }
+    }
+
+    /// Removed quotes, most recently removed first, until they're purged.
+    #[get("/trash")]
+    async fn trash(pgpool: web::Data<PgPool>, tenant: Tenant) -> HttpResponse {
+        match sqlx::query_as::<_, Quote>("SELECT id, author, quote, created_at, version, deleted_at, quote_tag_names(id) AS tags FROM quotes
+            WHERE deleted_at IS NOT NULL AND tenant = $1
+            ORDER BY deleted_at DESC, id ASC")
+            .bind(tenant.id())
+            .fetch_all(pgpool.get_ref()).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(quotes) => HttpResponse::Ok().json(quotes)
+        }
+    }
+
+    #[put("/restore/{id}")]
+    async fn restore(pgpool: web::Data<PgPool>, tenant: Tenant, id: web::Path<String>) -> HttpResponse {
+        let uuid = Uuid::from_str(&id);
+        if uuid.is_err() {
+            return HttpResponse::BadRequest().finish();
+        }
+        match sqlx::query_as::<_, Quote>("UPDATE quotes SET deleted_at = NULL WHERE id = $1 AND tenant = $2 AND deleted_at IS NOT NULL
+            RETURNING id, author, quote, created_at, version, deleted_at, quote_tag_names(id) AS tags")
+            .bind(uuid.unwrap())
+            .bind(tenant.id())
+            .fetch_optional(pgpool.get_ref()).await {
+            Err(e) => write_failure(e),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Ok(Some(quote)) => HttpResponse::Ok().insert_header(etag(&quote)).json(quote)
         }
     }
 
     #[derive(Debug, Deserialize)]
-    struct ListParams {
+    struct PickParams {
+        tag: Option<String>,
+        author: Option<String>,
+        /// Only for `/19/daily`, defaults to today in UTC.
+        date: Option<NaiveDate>
+    }
+    #[get("/random")]
+    async fn random(pgpool: web::Data<PgPool>, tenant: Tenant, params: web::Query<PickParams>) -> HttpResponse {
+        match sqlx::query_as::<_, Quote>("SELECT id, author, quote, created_at, version, deleted_at, quote_tag_names(id) AS tags
+            FROM quotes
+            WHERE deleted_at IS NULL AND tenant = $3
+                AND ($1::TEXT IS NULL OR $1 = ANY(quote_tag_names(id)))
+                AND ($2::TEXT IS NULL OR lower(author) = lower($2))
+            ORDER BY random()
+            LIMIT 1")
+            .bind(params.tag.as_deref().map(normalize_tag))
+            .bind(&params.author)
+            .bind(tenant.id())
+            .fetch_optional(pgpool.get_ref()).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Ok(Some(quote)) => HttpResponse::Ok().insert_header(etag(&quote)).json(quote)
+        }
+    }
+
+    /// Ranks the quotes by a hash of the date and their id, so every instance picks the same one all day long.
+    /// Only the quotes already there when the day started (UTC) take part, so one drafted during the
+    /// day can't take the pick over, unless there were none yet.
+    #[get("/daily")]
+    async fn daily(pgpool: web::Data<PgPool>, tenant: Tenant, params: web::Query<PickParams>) -> HttpResponse {
+        let date = params.date.unwrap_or_else(|| Utc::now().date_naive());
+        match sqlx::query_as::<_, Quote>("SELECT id, author, quote, created_at, version, deleted_at, quote_tag_names(id) AS tags
+            FROM quotes
+            WHERE deleted_at IS NULL AND tenant = $4
+                AND ($1::TEXT IS NULL OR $1 = ANY(quote_tag_names(id)))
+                AND ($2::TEXT IS NULL OR lower(author) = lower($2))
+            ORDER BY created_at < $3::DATE::TIMESTAMP AT TIME ZONE 'UTC' DESC, md5($3::DATE::TEXT || id::TEXT), id
+            LIMIT 1")
+            .bind(params.tag.as_deref().map(normalize_tag))
+            .bind(&params.author)
+            .bind(date)
+            .bind(tenant.id())
+            .fetch_optional(pgpool.get_ref()).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Ok(Some(quote)) => HttpResponse::Ok().insert_header(etag(&quote)).json(quote)
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct SearchParams {
+        #[serde(flatten)]
+        filter: SearchFilter,
         token: Option<String>
     }
+    #[get("/search")]
+    async fn search(pgpool: web::Data<PgPool>, tenant: Tenant, paginator: web::Data<Paginator>, params: web::Query<SearchParams>) -> HttpResponse {
+        let params = params.into_inner();
+        let cursor = match &params.token {
+            None => None,
+            Some(token) => match paginator.cursor::<SearchCursor>(token) {
+                None => return HttpResponse::BadRequest().finish(),
+                Some(cursor) => Some(cursor)
+            }
+        };
+        // The filter a search started with sticks to its next pages.
+        let filter = match &cursor {
+            None => params.filter,
+            Some(cursor) => cursor.filter.clone()
+        };
+
+        match store::search(pgpool.get_ref(), &tenant, &paginator, cursor, filter).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(page) => HttpResponse::Ok().json(page)
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ListParams {
+        token: Option<String>,
+        tag: Option<String>
+    }
     #[get("/list")]
     async fn list(
         pgpool: web::Data<PgPool>,
-        paginator_state: web::Data<Mutex<Paginator>>,
-        token: web::Query<ListParams>
+        tenant: Tenant,
+        paginator: web::Data<Paginator>,
+        params: web::Query<ListParams>
     ) -> HttpResponse {
-        let mut paginator_guard = paginator_state.lock().unwrap();
-
-        match token.into_inner().token.clone() {
-            None => {
-                match sqlx::query_as::<_, Quote>("SELECT * FROM quotes ORDER BY created_at ASC")
-                    .fetch_all(pgpool.get_ref()).await {
-                    Err(_) => HttpResponse::InternalServerError().finish(),
-                    Ok(quotes) => {
-                        if paginator_guard.traversed == true || paginator_guard.pages.len() == 0 {
-                            let mut paginator = Paginator::new();
-                            paginator.set_pages(quotes);
-
-                            let _ = mem::replace(&mut *paginator_guard, paginator);
-                        }
-                        match paginator_guard.next_page(None) {
-                            None => HttpResponse::BadRequest().finish(),
-                            Some(p) => HttpResponse::Ok().json(p)
-                        }
+        let cursor = match &params.token {
+            None => None,
+            Some(token) => match paginator.cursor::<Cursor>(token) {
+                None => return HttpResponse::BadRequest().finish(),
+                Some(cursor) => Some(cursor)
+            }
+        };
+        // The tag a listing started with sticks to its next pages.
+        let tag = match &cursor {
+            None => params.tag.as_deref().map(normalize_tag),
+            Some(cursor) => cursor.tag.clone()
+        };
+
+        match store::list(pgpool.get_ref(), &tenant, &paginator, cursor, tag).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            Ok(page) => HttpResponse::Ok().json(page)
+        }
+    }
+}
+
+mod bulk {
+    use std::io::{BufRead, BufReader, Read};
+
+    use actix_multipart::form::{tempfile::TempFile, MultipartForm};
+    use actix_web::{get, http::header, post, web, web::Bytes, HttpRequest, HttpResponse};
+    use async_stream::stream;
+    use futures_util::StreamExt;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use sqlx::{postgres::PgPool, types::{chrono::{DateTime, Utc}, Uuid}};
+    use crate::challenges::day_19::{is_duplicate, validate_quote, validate_tags, Quote, Tenant, TAG_SEPARATOR};
+
+    /// A quote as found in an import file, an `/19/export` backup keeps its ids, dates, versions and tags.
+    #[derive(Debug, Deserialize)]
+    struct ImportRow {
+        id: Option<Uuid>,
+        author: String,
+        quote: String,
+        created_at: Option<DateTime<Utc>>,
+        version: Option<i32>,
+        #[serde(default)]
+        tags: Vec<String>
+    }
+    impl ImportRow {
+        /// Trims the quote and holds it to the same rules as `/19/draft`.
+        fn validate(&mut self) -> Result<(), String> {
+            self.author = self.author.trim().to_string();
+            self.quote = self.quote.trim().to_string();
+            let mut errors = validate_quote(&self.author, &self.quote);
+            errors.extend(validate_tags(&mut self.tags));
+            if !errors.is_empty() {
+                return Err(errors.into_iter().map(|e| e.message).collect::<Vec<_>>().join(", "));
+            }
+            match self.version {
+                Some(version) if version < 1 => Err("version must be positive".to_string()),
+                _ => Ok(())
+            }
+        }
+    }
+
+    /// An `ImportRow` as a CSV row, which has a single column for the tags.
+    #[derive(Debug, Deserialize)]
+    struct CsvImportRow {
+        id: Option<Uuid>,
+        author: String,
+        quote: String,
+        created_at: Option<DateTime<Utc>>,
+        version: Option<i32>,
+        tags: Option<String>
+    }
+    impl From<CsvImportRow> for ImportRow {
+        fn from(row: CsvImportRow) -> Self {
+            let tags = row.tags.as_deref().map_or(vec![], |tags| tags.split(TAG_SEPARATOR)
+                .filter(|tag| !tag.trim().is_empty())
+                .map(str::to_string)
+                .collect());
+            ImportRow { id: row.id, author: row.author, quote: row.quote, created_at: row.created_at, version: row.version, tags }
+        }
+    }
+
+    /// A quote as a CSV row, its tags joined by `TAG_SEPARATOR`.
+    #[derive(Debug, Serialize)]
+    struct CsvQuote<'a> {
+        id: Uuid,
+        author: &'a str,
+        quote: &'a str,
+        created_at: DateTime<Utc>,
+        version: i32,
+        tags: String
+    }
+
+    /// A row number, from 1, and the row or why it couldn't be read.
+    type ParsedRow = (usize, Result<ImportRow, String>);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Format {
+        Csv,
+        Json,
+        Ndjson
+    }
+    impl Format {
+        fn from_mime(mime: &str) -> Option<Self> {
+            match mime.split(';').next().unwrap_or_default().trim() {
+                "text/csv" => Some(Format::Csv),
+                "application/json" => Some(Format::Json),
+                "application/x-ndjson" | "application/jsonl" => Some(Format::Ndjson),
+                _ => None
+            }
+        }
+
+        fn from_file_name(name: &str) -> Option<Self> {
+            match name.rsplit_once('.')?.1.to_lowercase().as_str() {
+                "csv" => Some(Format::Csv),
+                "json" => Some(Format::Json),
+                "ndjson" | "jsonl" => Some(Format::Ndjson),
+                _ => None
+            }
+        }
+
+        fn content_type(&self) -> &'static str {
+            match self {
+                Format::Csv => "text/csv",
+                Format::Json => "application/json",
+                Format::Ndjson => "application/x-ndjson"
+            }
+        }
+
+        /// Parses every row on its own so that one bad row doesn't hide the others.
+        fn rows(&self, file: impl Read) -> Result<Vec<ParsedRow>, String> {
+            match self {
+                Format::Csv => Ok(csv::Reader::from_reader(file)
+                    .deserialize::<CsvImportRow>()
+                    .enumerate()
+                    .map(|(i, row)| (i + 1, row.map(ImportRow::from).map_err(|e| e.to_string())))
+                    .collect()),
+                Format::Json => match serde_json::from_reader::<_, Vec<Value>>(file) {
+                    Err(e) => Err(e.to_string()),
+                    Ok(values) => Ok(values.into_iter()
+                        .enumerate()
+                        .map(|(i, value)| (i + 1, serde_json::from_value(value).map_err(|e| e.to_string())))
+                        .collect())
+                },
+                Format::Ndjson => Ok(BufReader::new(file)
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+                    .map(|(i, line)| (i + 1, match line {
+                        Err(e) => Err(e.to_string()),
+                        Ok(line) => serde_json::from_str(&line).map_err(|e| e.to_string())
+                    }))
+                    .collect())
+            }
+        }
+    }
+
+    #[derive(Debug, MultipartForm)]
+    struct ImportForm {
+        #[multipart(limit = "100MB")]
+        quotes: TempFile
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ImportParams {
+        #[serde(default)]
+        dry_run: bool
+    }
+
+    #[derive(Debug, Serialize)]
+    struct RowError {
+        row: usize,
+        message: String
+    }
+
+    #[derive(Debug, Serialize)]
+    struct ImportReport {
+        /// Rows that passed every check, they're only written when `committed`.
+        imported: usize,
+        committed: bool,
+        errors: Vec<RowError>
+    }
+
+    /// Imports the `quotes` file of the form in one transaction: any invalid row rolls back the
+    /// whole import, and so does a dry run once every row was checked against the database.
+    #[post("/import")]
+    async fn import(
+        pgpool: web::Data<PgPool>,
+        tenant: Tenant,
+        params: web::Query<ImportParams>,
+        MultipartForm(form): MultipartForm<ImportForm>
+    ) -> HttpResponse {
+        let format = form.quotes.content_type.as_ref()
+            .and_then(|mime| Format::from_mime(mime.essence_str()))
+            .or_else(|| form.quotes.file_name.as_deref().and_then(Format::from_file_name));
+        let format = match format {
+            None => return HttpResponse::UnsupportedMediaType().finish(),
+            Some(format) => format
+        };
+        let rows = match format.rows(form.quotes.file.as_file()) {
+            Err(e) => return HttpResponse::BadRequest().json(ImportReport {
+                imported: 0,
+                committed: false,
+                errors: vec![RowError { row: 0, message: e }]
+            }),
+            Ok(rows) => rows
+        };
+
+        let mut transaction = match pgpool.begin().await {
+            Err(e) => {
+                println!("Failed on starting the import: {:?}", e);
+                return HttpResponse::InternalServerError().finish();
+            },
+            Ok(transaction) => transaction
+        };
+        let mut imported = 0;
+        let mut errors = vec![];
+        for (row, parsed) in rows {
+            let quote = match parsed.and_then(|mut quote| quote.validate().map(|_| quote)) {
+                Err(message) => {
+                    errors.push(RowError { row, message });
+                    continue;
+                },
+                Ok(quote) => quote
+            };
+            // A savepoint per row, so that a failed insert doesn't abort the checks of the next ones.
+            let result = sqlx::query("SAVEPOINT import_row").execute(&mut *transaction).await;
+            let result = match result {
+                Err(e) => Err(e),
+                Ok(_) => sqlx::query("WITH inserted AS (
+                        INSERT INTO quotes (id, author, quote, created_at, version, tenant)
+                        VALUES ($1, $2, $3, COALESCE($4, CURRENT_TIMESTAMP), COALESCE($5, 1), $6)
+                        RETURNING id
+                    ), named AS (
+                        INSERT INTO tags (name) SELECT unnest($7::TEXT[]) FROM inserted
+                        ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+                        RETURNING id
+                    )
+                    INSERT INTO quote_tags (quote_id, tag_id) SELECT inserted.id, named.id FROM inserted, named")
+                    .bind(quote.id.unwrap_or_else(Uuid::new_v4))
+                    .bind(&quote.author)
+                    .bind(&quote.quote)
+                    .bind(quote.created_at)
+                    .bind(quote.version)
+                    .bind(tenant.id())
+                    .bind(&quote.tags)
+                    .execute(&mut *transaction).await
+            };
+            match result {
+                Err(e) => {
+                    let rollback = sqlx::query("ROLLBACK TO SAVEPOINT import_row").execute(&mut *transaction).await;
+                    if rollback.is_err() {
+                        println!("Failed on importing the quotes: {:?}", e);
+                        return HttpResponse::InternalServerError().finish();
+                    }
+                    let message = match e.as_database_error() {
+                        _ if is_duplicate(&e) => "this author already has this quote".to_string(),
+                        Some(db) if db.is_unique_violation() => "a quote with this id already exists".to_string(),
+                        Some(db) => db.message().to_string(),
+                        None => e.to_string()
+                    };
+                    errors.push(RowError { row, message });
+                },
+                Ok(_) => imported += 1
+            }
+        }
+
+        let committed = !params.dry_run && errors.is_empty();
+        let finished = match committed {
+            true => transaction.commit().await,
+            false => transaction.rollback().await
+        };
+        if let Err(e) = finished {
+            println!("Failed on finishing the import: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+        let report = ImportReport { imported, committed, errors };
+        match report.errors.is_empty() {
+            true => HttpResponse::Ok().json(report),
+            false => HttpResponse::UnprocessableEntity().json(report)
+        }
+    }
+
+    fn csv_line(quote: &Quote, headers: bool) -> Result<Bytes, String> {
+        let mut writer = csv::WriterBuilder::new().has_headers(headers).from_writer(vec![]);
+        writer.serialize(CsvQuote {
+            id: quote.id,
+            author: &quote.author,
+            quote: &quote.quote,
+            created_at: quote.created_at,
+            version: quote.version,
+            tags: quote.tags.join(&TAG_SEPARATOR.to_string())
+        }).map_err(|e| e.to_string())?;
+        writer.into_inner().map(Bytes::from).map_err(|e| e.to_string())
+    }
+
+    /// Streams every live quote, oldest first, as CSV, NDJSON or a JSON array depending on `Accept`.
+    #[get("/export")]
+    async fn export(pgpool: web::Data<PgPool>, tenant: Tenant, request: HttpRequest) -> HttpResponse {
+        let format = request.headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .and_then(|accept| accept.split(',').find_map(Format::from_mime))
+            .unwrap_or(Format::Json);
+        let pgpool = pgpool.get_ref().clone();
+        let tenant = tenant.id().to_string();
+
+        let body = stream! {
+            let mut quotes = sqlx::query_as::<_, Quote>("SELECT id, author, quote, created_at, version, deleted_at,
+                    quote_tag_names(id) AS tags
+                FROM quotes WHERE deleted_at IS NULL AND tenant = $1 ORDER BY created_at ASC, id ASC")
+                .bind(&tenant)
+                .fetch(&pgpool);
+            let mut first = true;
+            if format == Format::Json {
+                yield Ok(Bytes::from_static(b"["));
+            }
+            while let Some(quote) = quotes.next().await {
+                let quote = match quote {
+                    Err(e) => {
+                        println!("Failed on exporting the quotes: {:?}", e);
+                        yield Err(actix_web::error::ErrorInternalServerError(e));
+                        break;
+                    },
+                    Ok(quote) => quote
+                };
+                let chunk = match format {
+                    Format::Csv => csv_line(&quote, first),
+                    Format::Json => serde_json::to_vec(&quote)
+                        .map(|json| Bytes::from([if first { &b""[..] } else { &b","[..] }, &json].concat()))
+                        .map_err(|e| e.to_string()),
+                    Format::Ndjson => serde_json::to_vec(&quote)
+                        .map(|json| Bytes::from([&json[..], b"\n"].concat()))
+                        .map_err(|e| e.to_string())
+                };
+                first = false;
+                yield chunk.map_err(actix_web::error::ErrorInternalServerError);
+            }
+            if format == Format::Json {
+                yield Ok(Bytes::from_static(b"]"));
+            }
+        };
+
+        HttpResponse::Ok()
+            .content_type(format.content_type())
+            .streaming(body)
+    }
+}
+
+mod changes {
+    use std::time::Duration;
+
+    use actix_web::{get, http::header, web, web::Bytes, HttpResponse};
+    use async_stream::stream;
+    use tokio::{sync::broadcast::error::RecvError, time::timeout};
+    use crate::challenges::day_19::{ChangeFeed, Tenant};
+
+    /// Comment sent when nothing changed for that long, so proxies don't close an idle stream.
+    const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+    /// Server-sent events for every quote of the tenant created, updated or sent to the trash, each
+    /// one named after the change and carrying the quote. A `lagged` event tells how many changes a
+    /// stream too slow to keep up missed.
+    #[get("/changes")]
+    async fn stream_changes(feed: web::Data<ChangeFeed>, tenant: Tenant) -> HttpResponse {
+        let mut changes = feed.sender.subscribe();
+        let tenant = tenant.id().to_string();
+
+        let body = stream! {
+            yield Ok::<_, actix_web::Error>(Bytes::from_static(b": connected\n\n"));
+            loop {
+                match timeout(KEEP_ALIVE, changes.recv()).await {
+                    Err(_) => yield Ok(Bytes::from_static(b": keep-alive\n\n")),
+                    Ok(Err(RecvError::Closed)) => break,
+                    Ok(Err(RecvError::Lagged(missed))) => yield Ok(Bytes::from(format!("event: lagged\ndata: {}\n\n", missed))),
+                    Ok(Ok(change)) if change.tenant != tenant => {},
+                    Ok(Ok(change)) => match serde_json::to_string(&change.quote) {
+                        Err(e) => println!("Failed on sending a quote change: {:?}", e),
+                        Ok(json) => yield Ok(Bytes::from(format!("event: {}\ndata: {}\n\n", change.kind.name(), json)))
                     }
                 }
-            },
-            Some(token) => {
-                if token.len() != 16 {
-                    return HttpResponse::BadRequest().finish();
+            }
+        };
+
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .streaming(body)
+    }
+}
+
+/// `/19/graphql`: the quotes of `crud` for clients that pick their own fields, on the same queries.
+mod graphql {
+    use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse};
+    use async_graphql::{to_value, Context, EmptySubscription, Error, ErrorExtensions, Object, Result, Schema};
+    use sqlx::{postgres::PgPool, types::{chrono::{DateTime, Utc}, Uuid}};
+    use crate::{
+        auth::Role,
+        challenges::day_19::{
+            is_duplicate, normalize_tag, store, Cursor, NewQuote, PageList, Paginator, Quote, SearchCursor, SearchFilter, Tenant
+        }
+    };
+
+    pub type QuoteSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+    pub fn schema() -> QuoteSchema {
+        Schema::build(QueryRoot, MutationRoot, EmptySubscription).finish()
+    }
+
+    /// An error whose `code` extension tells clients what went wrong, as the status code does for `crud`.
+    fn failure(code: &'static str, message: &str) -> Error {
+        Error::new(message).extend_with(|_, e| e.set("code", code))
+    }
+
+    fn internal(e: sqlx::Error) -> Error {
+        println!("Failed on the quote query: {:?}", e);
+        failure("INTERNAL_SERVER_ERROR", "The quote query failed.")
+    }
+
+    fn write_failure(e: sqlx::Error) -> Error {
+        match is_duplicate(&e) {
+            true => failure("CONFLICT", "This author already has this quote."),
+            false => internal(e)
+        }
+    }
+
+    /// Tells a stale version from a missing quote once the compare-and-set matched nothing.
+    async fn conflict(pgpool: &PgPool, tenant: &Tenant, id: Uuid, expected: Option<i32>) -> Error {
+        if expected.is_none() {
+            return failure("NOT_FOUND", "No such quote.");
+        }
+        match store::version(pgpool, tenant, id).await {
+            Err(e) => internal(e),
+            Ok(None) => failure("NOT_FOUND", "No such quote."),
+            Ok(Some(version)) => failure("PRECONDITION_FAILED", "The quote has changed since this version.")
+                .extend_with(|_, e| e.set("version", version))
+        }
+    }
+
+    fn validated(mut input: NewQuote) -> Result<NewQuote> {
+        let errors = input.validate();
+        match errors.is_empty() {
+            true => Ok(input),
+            false => Err(failure("BAD_USER_INPUT", "The quote breaks the quote rules.")
+                .extend_with(|_, e| e.set("errors", to_value(&errors).unwrap_or_default())))
+        }
+    }
+
+    /// Mutations need the editor role, which the policy guarding the scope leaves in the request.
+    /// Callers without a role, or a scope left unguarded, can't write.
+    fn authorize(ctx: &Context<'_>) -> Result<()> {
+        match ctx.data_opt::<Role>() {
+            Some(role) if *role >= Role::Editor => Ok(()),
+            _ => Err(failure("FORBIDDEN", "Requires the Editor role"))
+        }
+    }
+
+    #[Object]
+    impl Quote {
+        async fn id(&self) -> Uuid {
+            self.id
+        }
+
+        async fn author(&self) -> &str {
+            &self.author
+        }
+
+        async fn quote(&self) -> &str {
+            &self.quote
+        }
+
+        async fn created_at(&self) -> DateTime<Utc> {
+            self.created_at
+        }
+
+        /// Pass it back to `undo` or `remove` to only apply them to this version.
+        async fn version(&self) -> i32 {
+            self.version
+        }
+
+        async fn tags(&self) -> &[String] {
+            &self.tags
+        }
+    }
+
+    #[Object(name = "QuotePage")]
+    impl PageList {
+        async fn quotes(&self) -> &[Quote] {
+            &self.quotes
+        }
+
+        async fn page(&self) -> usize {
+            self.page
+        }
+
+        /// Pass it as `after` for the next page, null on the last one.
+        async fn next_token(&self) -> Option<&str> {
+            self.next_token.as_deref()
+        }
+    }
+
+    pub struct QueryRoot;
+    #[Object]
+    impl QueryRoot {
+        async fn quote(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<Quote>> {
+            store::cite(ctx.data::<web::Data<PgPool>>()?, ctx.data::<Tenant>()?, id).await.map_err(internal)
+        }
+
+        /// The live quotes oldest first, the tag of the first page sticks to the next ones.
+        async fn quotes(&self, ctx: &Context<'_>, after: Option<String>, tag: Option<String>) -> Result<PageList> {
+            let paginator = ctx.data::<web::Data<Paginator>>()?;
+            let cursor = match after {
+                None => None,
+                Some(token) => match paginator.cursor::<Cursor>(&token) {
+                    None => return Err(failure("BAD_USER_INPUT", "Invalid page token.")),
+                    Some(cursor) => Some(cursor)
                 }
-                match paginator_guard.next_page(Some(token)) {
-                    None => HttpResponse::BadRequest().finish(),
-                    Some(p) => HttpResponse::Ok().json(p)
+            };
+            let tag = match &cursor {
+                None => tag.as_deref().map(normalize_tag),
+                Some(cursor) => cursor.tag.clone()
+            };
+            store::list(ctx.data::<web::Data<PgPool>>()?, ctx.data::<Tenant>()?, paginator, cursor, tag).await.map_err(internal)
+        }
+
+        async fn search(
+            &self,
+            ctx: &Context<'_>,
+            q: Option<String>,
+            author: Option<String>,
+            since: Option<DateTime<Utc>>,
+            until: Option<DateTime<Utc>>,
+            after: Option<String>
+        ) -> Result<PageList> {
+            let paginator = ctx.data::<web::Data<Paginator>>()?;
+            let cursor = match after {
+                None => None,
+                Some(token) => match paginator.cursor::<SearchCursor>(&token) {
+                    None => return Err(failure("BAD_USER_INPUT", "Invalid page token.")),
+                    Some(cursor) => Some(cursor)
                 }
+            };
+            let filter = match &cursor {
+                None => SearchFilter { q, author, since, until },
+                Some(cursor) => cursor.filter.clone()
+            };
+            store::search(ctx.data::<web::Data<PgPool>>()?, ctx.data::<Tenant>()?, paginator, cursor, filter).await.map_err(internal)
+        }
+    }
+
+    pub struct MutationRoot;
+    #[Object]
+    impl MutationRoot {
+        async fn draft(&self, ctx: &Context<'_>, input: NewQuote) -> Result<Quote> {
+            authorize(ctx)?;
+            let input = validated(input)?;
+            store::draft(ctx.data::<web::Data<PgPool>>()?, ctx.data::<Tenant>()?, &input).await.map_err(write_failure)
+        }
+
+        /// Edits the quote, only if it's still at `version` when one is given.
+        async fn undo(&self, ctx: &Context<'_>, id: Uuid, input: NewQuote, version: Option<i32>) -> Result<Quote> {
+            authorize(ctx)?;
+            let input = validated(input)?;
+            let (pgpool, tenant) = (ctx.data::<web::Data<PgPool>>()?, ctx.data::<Tenant>()?);
+            match store::undo(pgpool, tenant, id, version.as_ref().map(std::slice::from_ref), &input).await {
+                Err(e) => Err(write_failure(e)),
+                Ok(None) => Err(conflict(pgpool, tenant, id, version).await),
+                Ok(Some(quote)) => Ok(quote)
             }
         }
+
+        /// Sends the quote to the trash, only if it's still at `version` when one is given.
+        async fn remove(&self, ctx: &Context<'_>, id: Uuid, version: Option<i32>) -> Result<Quote> {
+            authorize(ctx)?;
+            let (pgpool, tenant) = (ctx.data::<web::Data<PgPool>>()?, ctx.data::<Tenant>()?);
+            match store::remove(pgpool, tenant, id, version.as_ref().map(std::slice::from_ref)).await {
+                Err(e) => Err(internal(e)),
+                Ok(None) => Err(conflict(pgpool, tenant, id, version).await),
+                Ok(Some(quote)) => Ok(quote)
+            }
+        }
+    }
+
+    #[post("/graphql")]
+    async fn execute(
+        schema: web::Data<QuoteSchema>,
+        pgpool: web::Data<PgPool>,
+        paginator: web::Data<Paginator>,
+        tenant: Tenant,
+        req: HttpRequest,
+        request: web::Json<async_graphql::Request>
+    ) -> HttpResponse {
+        let mut request = request.into_inner().data(pgpool).data(paginator).data(tenant);
+        if let Some(role) = req.extensions().get::<Role>().copied() {
+            request = request.data(role);
+        }
+        HttpResponse::Ok().json(schema.execute(request).await)
     }
 }
 
 pub fn scope() -> Scope {
     web::scope("/19")
+        .app_data(web::JsonConfig::default().error_handler(|e, _| {
+            let response = Problem::new(StatusCode::BAD_REQUEST, "Malformed quote", &e).response();
+            InternalError::from_response(e, response).into()
+        }))
         .service(crud::reset)
         .service(crud::draft)
         .service(crud::remove)
         .service(crud::cite)
         .service(crud::undo)
         .service(crud::list)
+        .service(crud::search)
+        .service(crud::history)
+        .service(crud::revert)
+        .service(crud::trash)
+        .service(crud::restore)
+        .service(crud::tag_counts)
+        .service(crud::random)
+        .service(crud::daily)
+        .service(bulk::import)
+        .service(bulk::export)
+        .service(changes::stream_changes)
+        .service(graphql::execute)
+        .app_data(web::Data::new(graphql::schema()))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use sqlx::types::{chrono::Utc, Uuid};
+
+    use super::{graphql, Cursor, Paginator, SearchCursor, SearchFilter};
+
+    fn cursor() -> Cursor {
+        Cursor { created_at: Utc::now(), id: Uuid::new_v4(), page: 2, tag: Some("winter".to_string()) }
+    }
+
+    #[test]
+    fn page_token_round_trips() {
+        let paginator = Paginator::new(b"secret", 3);
+        let cursor = cursor();
+        let read = paginator.cursor::<Cursor>(&paginator.token(&cursor)).unwrap();
+        assert_eq!((read.created_at, read.id, read.page, read.tag), (cursor.created_at, cursor.id, cursor.page, cursor.tag));
+
+        let search = SearchCursor {
+            rank: 0.0607927,
+            created_at: Utc::now(),
+            id: Uuid::new_v4(),
+            page: 3,
+            filter: SearchFilter { q: Some("christmas".to_string()), ..SearchFilter::default() }
+        };
+        let read = paginator.cursor::<SearchCursor>(&paginator.token(&search)).unwrap();
+        assert_eq!((read.rank, read.id, read.filter.q), (search.rank, search.id, search.filter.q));
+    }
+
+    #[test]
+    fn modified_page_token_is_rejected() {
+        let paginator = Paginator::new(b"secret", 3);
+        let token = paginator.token(&cursor());
+        let (payload, signature) = token.split_once('.').unwrap();
+
+        let mut json: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        json["tag"] = "summer".into();
+        let forged = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&json).unwrap());
+        assert!(paginator.cursor::<Cursor>(&format!("{}.{}", forged, signature)).is_none());
+
+        assert!(Paginator::new(b"other secret", 3).cursor::<Cursor>(&token).is_none());
+        for malformed in ["", "1", ".", payload, &format!("{}.", payload), &format!("{}.{}", payload, "!")] {
+            assert!(paginator.cursor::<Cursor>(malformed).is_none(), "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn page_token_is_bound_to_its_listing() {
+        let paginator = Paginator::new(b"secret", 3);
+        assert!(paginator.cursor::<SearchCursor>(&paginator.token(&cursor())).is_none());
+    }
+
+    #[actix_web::test]
+    async fn graphql_schema_exposes_quotes() {
+        let response = graphql::schema().execute("{
+            __schema { queryType { fields { name } } mutationType { fields { name } } }
+            __type(name: \"Quote\") { fields { name } }
+        }").await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let data = response.data.into_json().unwrap();
+        let names = |fields: &serde_json::Value| fields.as_array().unwrap()
+            .iter()
+            .map(|field| field["name"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names(&data["__schema"]["queryType"]["fields"]), ["quote", "quotes", "search"]);
+        assert_eq!(names(&data["__schema"]["mutationType"]["fields"]), ["draft", "undo", "remove"]);
+        assert_eq!(names(&data["__type"]["fields"]), ["id", "author", "quote", "createdAt", "version", "tags"]);
+    }
 }
@@ -1 +1,3 @@
-pub mod challenges;
\ No newline at end of file
+pub mod auth;
+pub mod challenges;
+pub mod problem;
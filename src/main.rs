@@ -3,9 +3,18 @@ use std::{sync::Mutex, time::Duration};
 use actix_files::Files;
 use leaky_bucket::RateLimiter;
 use shuttle_runtime::SecretStore;
-use shuttlings_cch24::challenges::{self, day_12::Board, day_9::MilkBucket, day_19::Paginator};
+use shuttlings_cch24::{
+    auth::{Authenticator, Policy, Role},
+    challenges::{
+        self,
+        day_12::Board,
+        day_16::{GiftConfig, GiftCookie, GiftKeys, GiftSchema, Revocations, MAX_GIFT_BYTES},
+        day_9::MilkBucket,
+        day_19::{self, ChangeFeed, Paginator, Tenants, PAGE_SIZE, TRASH_RETENTION}
+    }
+};
 
-use actix_web::web::{self, ServiceConfig};
+use actix_web::{cookie::SameSite, http::Method, web::{self, ServiceConfig}};
 use shuttle_actix_web::ShuttleActixWeb;
 use tera::Tera;
 
@@ -19,12 +28,80 @@ async fn main(
         .await
         .expect("Failed on running the migrations.");
 
-    let jwt_secret = secrets.get("PUB_PEM").expect("Unable to read PUB_PEM secret").as_bytes().to_vec();
+    let pool = web::Data::new(pool);
+    let mut gift_keys = GiftKeys::from_pems(
+        secrets.get("GIFT_HMAC_SECRET").expect("Unable to read GIFT_HMAC_SECRET secret").as_bytes(),
+        secrets.get("GIFT_PRIVATE_PEM").as_deref(),
+        &secrets.get("PUB_PEM").expect("Unable to read PUB_PEM secret")
+    ).expect("Unable to load the gift keys.");
+    if let Some(jwks) = secrets.get("GIFT_PUBLIC_JWKS") {
+        gift_keys = gift_keys.with_jwks(&jwks).expect("Unable to load the gift JWKS.");
+    }
+    if let Some(key) = secrets.get("GIFT_ENCRYPTION_KEY") {
+        gift_keys = gift_keys.with_encryption_key(&key).expect("Unable to load the gift encryption key.");
+    }
+    let gift_keys = web::Data::new(gift_keys);
+    let mut authenticator = Authenticator::new(&secrets.get("AUTH_API_KEYS").unwrap_or_default())
+        .expect("Unable to load the API keys.")
+        .with_anonymous(secrets.get("AUTH_ANONYMOUS_ROLE").map(|role| role.parse().expect("Unknown AUTH_ANONYMOUS_ROLE.")));
+    // Bearer tokens are only accepted once we know who issues them and with which keys.
+    if let (Some(issuer), Some(kids)) = (secrets.get("AUTH_ISSUER"), secrets.get("AUTH_TOKEN_KIDS")) {
+        let kids = kids.split(',').map(str::trim).filter(|kid| !kid.is_empty()).map(str::to_string).collect();
+        authenticator = authenticator.with_jwt(gift_keys.clone(), issuer, kids, secrets.get("AUTH_AUDIENCE"));
+    }
+    let authenticator = web::Data::new(authenticator);
+    let gift_schema = web::Data::new(GiftSchema::new(
+        secrets.get("GIFT_SCHEMA").as_deref(),
+        secrets.get("GIFT_MAX_BYTES").and_then(|max| max.parse().ok()).unwrap_or(MAX_GIFT_BYTES)
+    ).expect("Unable to load the gift schema."));
+    let revocations = web::Data::new(match secrets.get("GIFT_REVOCATIONS_IN_MEMORY").as_deref() {
+        Some("true") => Revocations::in_memory(),
+        _ => Revocations::persistent(pool.get_ref().clone())
+            .await
+            .expect("Failed on loading the revoked gifts.")
+    });
+    let mut gift_cookie = GiftCookie::default();
+    if let Some(http_only) = secrets.get("GIFT_COOKIE_HTTP_ONLY") {
+        gift_cookie.http_only = http_only != "false";
+    }
+    if let Some(secure) = secrets.get("GIFT_COOKIE_SECURE") {
+        gift_cookie.secure = secure != "false";
+    }
+    if let Some(same_site) = secrets.get("GIFT_COOKIE_SAME_SITE") {
+        gift_cookie.same_site = match same_site.to_lowercase().as_str() {
+            "lax" => SameSite::Lax,
+            "none" => SameSite::None,
+            _ => SameSite::Strict
+        };
+    }
+    if let Some(path) = secrets.get("GIFT_COOKIE_PATH") {
+        gift_cookie.path = path;
+    }
+    gift_cookie.max_age = secrets.get("GIFT_COOKIE_MAX_AGE").and_then(|max_age| max_age.parse().ok());
+    let gift_config = web::Data::new(GiftConfig {
+        ttl: secrets.get("GIFT_TTL").and_then(|ttl| ttl.parse().ok()),
+        issuer: secrets.get("GIFT_ISSUER"),
+        audience: secrets.get("GIFT_AUDIENCE"),
+        cookie: gift_cookie,
+        refresh_within: secrets.get("GIFT_REFRESH_WITHIN").and_then(|within| within.parse().ok())
+    });
     let milk_bucket = web::Data::new(MilkBucket {
         bucket: Mutex::new(RateLimiter::builder().max(5).initial(5).interval(Duration::from_secs(1)).build())
     });
     let milk_cookie_board = web::Data::new(Mutex::new(Board::new()));
-    let paginator = web::Data::new(Mutex::new(Paginator::new()));
+    let paginator = web::Data::new(Paginator::new(
+        secrets.get("LIST_CURSOR_SECRET").expect("Unable to read LIST_CURSOR_SECRET secret").as_bytes(),
+        secrets.get("LIST_PAGE_SIZE").and_then(|size| size.parse().ok()).unwrap_or(PAGE_SIZE)
+    ));
+    let tenants = web::Data::new(Tenants::new(secrets.get("QUOTE_TRUST_TENANT_HEADER").as_deref() == Some("true")));
+    day_19::spawn_purge(
+        pool.get_ref().clone(),
+        secrets.get("TRASH_RETENTION_DAYS")
+            .and_then(|days| days.parse::<u64>().ok())
+            .map_or(TRASH_RETENTION, |days| Duration::from_secs(days * 24 * 60 * 60)),
+        Duration::from_secs(60 * 60)
+    );
+    let changes = web::Data::new(ChangeFeed::spawn(pool.get_ref().clone()));
     let tera = match Tera::new("./assets/*.html") {
         Err(e) => {
             println!("Parsing error: {:?}", e);
@@ -38,15 +115,33 @@ async fn main(
         cfg.service(challenges::intro::seek);
         cfg.service(challenges::day_2::scope());
         cfg.service(challenges::day_5::scope());
-        cfg.service(challenges::day_9::scope())
+        cfg.app_data(authenticator);
+        cfg.service(challenges::day_9::scope()
+            .wrap(Policy::new().route(Method::POST, "/9/refill", Role::Admin)))
             .app_data(milk_bucket);
-        cfg.service(challenges::day_12::scope())
-            .app_data(milk_cookie_board.clone());
-        cfg.service(challenges::day_16::scope())
-            .app_data(web::Data::new(jwt_secret));
-        cfg.service(challenges::day_19::scope())
-            .app_data(web::Data::new(pool))
-            .app_data(paginator);
+        cfg.service(challenges::day_12::scope()
+            .wrap(Policy::new().route(Method::POST, "/12/reset", Role::Admin)))
+            .app_data(milk_cookie_board.clone())
+            .app_data(pool.clone());
+        cfg.service(challenges::day_16::scope()
+            .wrap(Policy::new()
+                .route(Method::POST, "/16/rotate", Role::Admin)
+                .route(Method::DELETE, "/16/keys", Role::Admin)))
+            .app_data(gift_keys)
+            .app_data(gift_config)
+            .app_data(gift_schema)
+            .app_data(revocations);
+        cfg.service(challenges::day_19::scope()
+            .wrap(Policy::new()
+                .route(Method::POST, "/19/reset", Role::Admin)
+                // Reads stay public, mutations check the editor role themselves.
+                .open(Method::POST, "/19/graphql")
+                .open(Method::GET, "/19")
+                .rest(Role::Editor)))
+            .app_data(pool.clone())
+            .app_data(paginator)
+            .app_data(tenants)
+            .app_data(changes);
         cfg.service(challenges::day_23::scope())
             .app_data(web::Data::new(tera));
     };
@@ -0,0 +1,30 @@
+use actix_web::{http::StatusCode, HttpResponse, HttpResponseBuilder};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// RFC 7807 problem details.
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(flatten)]
+    extensions: Map<String, Value>
+}
+impl Problem {
+    pub fn new(status: StatusCode, title: &'static str, detail: impl ToString) -> Self {
+        Problem { title, status: status.as_u16(), detail: detail.to_string(), extensions: Map::new() }
+    }
+
+    /// Adds a member of its own to the problem, such as the rules the request broke.
+    pub fn with(mut self, member: &str, value: impl Serialize) -> Self {
+        self.extensions.insert(member.to_string(), serde_json::to_value(value).expect("Extension is serializable"));
+        self
+    }
+
+    pub fn response(self) -> HttpResponse {
+        HttpResponseBuilder::new(StatusCode::from_u16(self.status).unwrap_or(StatusCode::BAD_REQUEST))
+            .content_type("application/problem+json")
+            .json(self)
+    }
+}